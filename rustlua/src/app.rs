@@ -1,5 +1,7 @@
 pub mod cmdline;
 pub mod fs;
+pub mod game;
 pub mod jslog;
+pub mod lua;
 pub mod res;
 pub mod sys;
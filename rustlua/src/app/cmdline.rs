@@ -1,7 +1,22 @@
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
 use anyhow::Context;
 use clap::Parser;
 
-use crate::app::fs::HOME_DIR;
+use crate::app::fs::{EntryType, HOME_DIR};
+
+use lexer::{Token, Word};
+
+mod cond;
+mod file;
+pub mod job;
+mod lexer;
+mod repl;
+mod script;
+mod text;
 
 #[derive(clap::Parser)]
 struct CommandParser {
@@ -21,26 +36,628 @@ enum Commands {
     },
     /// List files
     Ls { paths: Vec<String> },
+    /// Print the contents of files ("-" or none: the input)
+    Cat { paths: Vec<String> },
+    /// Print arguments
+    Echo {
+        /// Do not print the trailing newline
+        #[arg(short = 'n')]
+        no_newline: bool,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Print lines containing PATTERN (a fixed string)
+    Grep {
+        /// Ignore case
+        #[arg(short = 'i')]
+        ignore_case: bool,
+        /// Print non-matching lines
+        #[arg(short = 'v')]
+        invert: bool,
+        /// Prefix each line with its line number
+        #[arg(short = 'n')]
+        line_number: bool,
+        /// Print only the number of matching lines
+        #[arg(short = 'c')]
+        count: bool,
+        pattern: String,
+        /// Files to search ("-" or none: the input)
+        paths: Vec<String>,
+    },
+    /// Do nothing successfully (exit status 0)
+    True,
+    /// Do nothing unsuccessfully (exit status 1)
+    False,
+    /// Evaluate a conditional expression (exit status 0: true, 1: false).
+    /// -e/-f/-d/-s FILE, -n/-z STRING, S1 =/!= S2,
+    /// N1 -eq/-ne/-lt/-le/-gt/-ge N2, ! EXPR
+    #[command(disable_help_flag = true)]
+    Test {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Same as `test` with "]" as the last argument
+    #[command(name = "[", disable_help_flag = true)]
+    Bracket {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Make directories
+    Mkdir {
+        /// Make parent directories as needed (no error if existing)
+        #[arg(short = 'p')]
+        parents: bool,
+        #[arg(required = true)]
+        dirs: Vec<String>,
+    },
+    /// Remove files or directories
+    Rm {
+        /// Remove directories and their contents
+        #[arg(short = 'r', short_alias = 'R')]
+        recursive: bool,
+        /// Ignore nonexistent files
+        #[arg(short = 'f')]
+        force: bool,
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Move (rename) files: SOURCE... DEST
+    Mv {
+        #[arg(required = true, num_args = 2..)]
+        paths: Vec<String>,
+    },
+    /// Copy files: SOURCE... DEST
+    Cp {
+        /// Copy directories recursively
+        #[arg(short = 'r', short_alias = 'R')]
+        recursive: bool,
+        #[arg(required = true, num_args = 2..)]
+        paths: Vec<String>,
+    },
+    /// Create empty files or update their modification time
+    Touch {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Show memory usage
+    Mem,
+    /// Run a Lua script file.
+    /// Enter Lua REPL mode if neither a script nor -e is given
+    /// ("exit" to return).
+    Lua {
+        /// Execute a string (can be specified multiple times)
+        #[arg(short = 'e', value_name = "CODE")]
+        exec: Vec<String>,
+        /// Lua script file and the arguments passed to it (`arg` and `...`).
+        /// Everything after the script goes to the script
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "SCRIPT [ARGS]"
+        )]
+        script_args: Vec<String>,
+    },
+    /// Run a Lua script as a job stepped every frame.
+    /// Append "&" to run it in the background
+    Run {
+        /// Lua script file and the arguments passed to it (`arg` and `...`).
+        /// Everything after the script goes to the script
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "SCRIPT [ARGS]"
+        )]
+        script_args: Vec<String>,
+    },
+    /// Run a shell script. The working directory is restored at the end
+    Sh {
+        /// Script file and the positional parameters ($1, $2, ...).
+        /// Everything after the script goes to the script
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "SCRIPT [ARGS]"
+        )]
+        script_args: Vec<String>,
+    },
+    /// Run a shell script in the current shell
+    #[command(visible_alias = ".")]
+    Source {
+        /// Script file and the positional parameters ($1, $2, ...).
+        /// Everything after the script goes to the script
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "SCRIPT [ARGS]"
+        )]
+        script_args: Vec<String>,
+    },
+    /// Set options of the running script: -e (exit on error) or +e.
+    /// Show them if none is given
+    #[command(disable_help_flag = true)]
+    Set {
+        #[arg(allow_hyphen_values = true)]
+        options: Vec<String>,
+    },
+    /// List jobs (id, status, CPU time)
+    Jobs,
+    /// Stop a job
+    Kill { id: u32 },
+    /// Show the output of a job and keep printing it
+    /// (default: the most recent job)
+    Fg { id: Option<u32> },
+    /// Buffer the output of a job again (default: the foreground job)
+    Bg { id: Option<u32> },
+    /// Control the Lua game loop (load/update/draw)
+    Game {
+        #[command(subcommand)]
+        op: GameOp,
+    },
+    /// Control the persistent Lua session
+    Session {
+        #[command(subcommand)]
+        op: SessionOp,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum GameOp {
+    /// Show the state of the game loop
+    Status,
+    /// Call load() again and restart the loop
+    Restart,
+    /// Stop the loop
+    Stop,
+}
+
+#[derive(clap::Subcommand)]
+enum SessionOp {
+    /// Show the state of the Lua session
+    Status,
+    /// Discard all globals and loaded modules
+    Reset {
+        /// Standard libraries to load (comma separated, or "all")
+        #[arg(long, value_name = "LIST")]
+        libs: Option<String>,
+        /// Confine file access of io/os to this directory
+        #[arg(long, value_name = "DIR", conflicts_with = "no_sandbox")]
+        sandbox: Option<String>,
+        /// Allow io/os to access any path
+        #[arg(long)]
+        no_sandbox: bool,
+    },
+    /// Show or set the execution limits of each run (0: unlimited)
+    Limit {
+        /// Maximum number of VM instructions
+        #[arg(long, value_name = "N")]
+        instructions: Option<u64>,
+        /// Timeout in milliseconds
+        #[arg(long, value_name = "MS")]
+        timeout: Option<u64>,
+        /// Maximum size of the Lua heap in bytes
+        #[arg(long, value_name = "BYTES")]
+        memory: Option<usize>,
+    },
+    /// Show or edit the module search roots of require()
+    Path {
+        #[command(subcommand)]
+        op: Option<PathOp>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum PathOp {
+    /// Add a search root
+    Add { dir: String },
+    /// Remove a search root
+    Remove { dir: String },
+}
+
+/// Standard input and output of a command.
+struct Stdio<'a> {
+    /// Redirected input (`None`: the terminal, which cannot be read)
+    input: Option<Vec<u8>>,
+    out: &'a mut dyn Write,
+    /// `true` if `out` is the terminal
+    is_terminal: bool,
+}
+
+impl Stdio<'_> {
+    fn is_redirected(&self) -> bool {
+        self.input.is_some() || !self.is_terminal
+    }
+}
+
+/// Error of a command with an exit status other than 1.
+/// Only the message (if any) is printed.
+#[derive(Debug)]
+struct ExitStatus {
+    code: i32,
+    message: String,
+}
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ExitStatus {}
+
+/// `Ok` if `code` is 0, otherwise a silent error with `code`.
+fn exit_status(code: i32) -> anyhow::Result<()> {
+    if code == 0 {
+        return Ok(());
+    }
+
+    Err(ExitStatus {
+        code,
+        message: String::new(),
+    }
+    .into())
+}
+
+/// Print the error of a command and return its exit status.
+fn report(res: anyhow::Result<()>) -> i32 {
+    let err = match res {
+        Ok(()) => return 0,
+        Err(err) => err,
+    };
+    if let Some(status) = err.downcast_ref::<ExitStatus>() {
+        if !status.message.is_empty() {
+            println!("{}", status.message);
+        }
+        return status.code;
+    }
+
+    println!("{err:#}");
+    match err.downcast_ref::<clap::Error>() {
+        // 0 for --help
+        Some(err) => err.exit_code(),
+        None => 1,
+    }
+}
+
+thread_local! {
+    /// `$?`
+    static STATUS: Cell<i32> = const { Cell::new(0) };
 }
 
-pub fn exec(cmdline: &str) -> anyhow::Result<()> {
-    let cd = std::env::current_dir()?;
+/// Value of `$name`.
+fn lookup(name: &str) -> Vec<String> {
+    match name {
+        "?" => vec![STATUS.get().to_string()],
+        "@" => script::params(),
+        name => {
+            vec![script::param(name).unwrap_or_else(|| std::env::var(name).unwrap_or_default())]
+        }
+    }
+}
+
+/// A command of a pipeline with its redirections.
+#[derive(Default)]
+struct Stage {
+    words: Vec<Word>,
+    input: Option<Word>,
+    /// Path and `true` to append
+    output: Option<(Word, bool)>,
+}
+
+/// How a pipeline is run after the previous one.
+#[derive(Clone, Copy)]
+enum Connector {
+    /// `;`, `&` (or the first one)
+    Always,
+    /// `&&`
+    IfSuccess,
+    /// `||`
+    IfFailure,
+}
+
+struct Pipeline {
+    connector: Connector,
+    stages: Vec<Stage>,
+    background: bool,
+}
+
+fn parse_pipeline(tokens: Vec<Token>) -> anyhow::Result<Vec<Stage>> {
+    let mut stages = vec![Stage::default()];
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        let stage = stages.last_mut().unwrap();
+        match token {
+            Token::Word(word) => stage.words.push(word),
+            Token::Pipe => {
+                anyhow::ensure!(
+                    !stage.words.is_empty(),
+                    "syntax error near unexpected token '|'"
+                );
+                stages.push(Stage::default());
+            }
+            Token::RedirectIn | Token::RedirectOut | Token::Append => {
+                let path = match tokens.next() {
+                    Some(Token::Word(path)) => path,
+                    Some(next) => anyhow::bail!("syntax error near unexpected token '{next}'"),
+                    None => anyhow::bail!("syntax error near unexpected token 'newline'"),
+                };
+                match token {
+                    Token::RedirectIn => stage.input = Some(path),
+                    Token::RedirectOut => stage.output = Some((path, false)),
+                    _ => stage.output = Some((path, true)),
+                }
+            }
+            token => anyhow::bail!("syntax error near unexpected token '{token}'"),
+        }
+    }
+    // a redirection only (`> file`) is allowed
+    anyhow::ensure!(
+        stages.len() == 1 || !stages.last().unwrap().words.is_empty(),
+        "syntax error: unexpected end of line after '|'"
+    );
+
+    Ok(stages)
+}
+
+/// Split `tokens` at `;`, `&`, `&&` and `||`.
+fn parse_list(tokens: Vec<Token>) -> anyhow::Result<Vec<Pipeline>> {
+    let mut list = Vec::new();
+    let mut connector = Connector::Always;
+    let mut current = Vec::new();
+    for token in tokens {
+        let next = match token {
+            Token::Semicolon | Token::Background => Connector::Always,
+            Token::And => Connector::IfSuccess,
+            Token::Or => Connector::IfFailure,
+            token => {
+                current.push(token);
+                continue;
+            }
+        };
+        anyhow::ensure!(
+            !current.is_empty(),
+            "syntax error near unexpected token '{token}'"
+        );
+        list.push(Pipeline {
+            connector,
+            stages: parse_pipeline(std::mem::take(&mut current))?,
+            background: token == Token::Background,
+        });
+        connector = next;
+    }
+
+    if current.is_empty() {
+        anyhow::ensure!(
+            matches!(connector, Connector::Always),
+            "syntax error: unexpected end of line"
+        );
+    } else {
+        list.push(Pipeline {
+            connector,
+            stages: parse_pipeline(current)?,
+            background: false,
+        });
+    }
+
+    Ok(list)
+}
+
+fn open_output(path: &str, append: bool) -> anyhow::Result<std::fs::File> {
+    std::fs::File::options()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("{path}: {}", file::io_msg(&e)))
+}
+
+/// Execute a command line and return its exit status (also set to `$?`).
+pub fn exec(cmdline: &str) -> i32 {
+    if repl::is_active() {
+        return report(repl::input(cmdline));
+    }
+    let cmdline = if job::is_reading() {
+        match cmdline.strip_prefix('!') {
+            Some(cmdline) => cmdline,
+            None => {
+                job::input(cmdline);
+                return 0;
+            }
+        }
+    } else {
+        cmdline
+    };
+
+    let cd = std::env::current_dir().unwrap_or_default();
     println!("{}$ {cmdline}", cd.to_string_lossy());
 
-    let arg0 = std::iter::once("CMDLINE");
-    let tokens = cmdline.split_whitespace();
-    let parsed = CommandParser::try_parse_from(arg0.chain(tokens))?;
+    if let Err(err) = run_line(cmdline, &mut std::io::stdout(), true) {
+        println!("{err:#}");
+        STATUS.set(2);
+    }
+
+    STATUS.get()
+}
+
+/// Run a command line with `out` as the terminal.
+/// `Err` is a syntax error (found before running anything).
+/// `Break` if the running script is stopped by `set -e`.
+fn run_line(line: &str, out: &mut dyn Write, is_terminal: bool) -> anyhow::Result<ControlFlow<()>> {
+    let list = lexer::tokenize(line).and_then(parse_list)?;
+
+    for (i, pipeline) in list.iter().enumerate() {
+        let run = match pipeline.connector {
+            Connector::Always => true,
+            Connector::IfSuccess => STATUS.get() == 0,
+            Connector::IfFailure => STATUS.get() != 0,
+        };
+        if !run {
+            continue;
+        }
+        let status = run_pipeline(&pipeline.stages, pipeline.background, out, is_terminal);
+        STATUS.set(status);
+
+        // a failure followed by `&&` or `||` is not an error
+        let is_checked = list
+            .get(i + 1)
+            .is_none_or(|next| matches!(next.connector, Connector::Always));
+        if status != 0 && is_checked && script::errexit() {
+            return Ok(ControlFlow::Break(()));
+        }
+    }
+
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Run the commands of a pipeline in order. Each command runs to the end
+/// and its output is passed to the next one.
+/// The exit status is that of the last command.
+fn run_pipeline(
+    stages: &[Stage],
+    background: bool,
+    term: &mut dyn Write,
+    is_terminal: bool,
+) -> i32 {
+    let mut input = None;
+    let mut status = 0;
+    for (i, stage) in stages.iter().enumerate() {
+        let is_last = i + 1 == stages.len();
+        let res = (|| -> anyhow::Result<()> {
+            let mut stage_input = input.take();
+            if !is_last {
+                // also when this command fails
+                input = Some(Vec::new());
+            }
+            let words: Vec<String> = stage.words.iter().flat_map(|w| w.expand(lookup)).collect();
+            if let Some(path) = &stage.input {
+                let path = path.expand(lookup).join(" ");
+                let data = std::fs::read(&path)
+                    .map_err(|e| anyhow::anyhow!("{path}: {}", file::io_msg(&e)))?;
+                stage_input = Some(data);
+            }
+            let mut pipe = Vec::new();
+            let mut output = match &stage.output {
+                Some((path, append)) => Some(open_output(&path.expand(lookup).join(" "), *append)?),
+                None => None,
+            };
+            let (out, is_terminal): (&mut dyn Write, bool) = match &mut output {
+                Some(output) => (output, false),
+                None if is_last => (&mut *term, is_terminal),
+                None => (&mut pipe, false),
+            };
+            let mut stdio = Stdio {
+                input: stage_input,
+                out,
+                is_terminal,
+            };
+            let res = run_command(&words, background, &mut stdio);
+            stdio.out.flush()?;
+            // the next command reads nothing if the output went to a file
+            if !is_last {
+                input = Some(pipe);
+            }
+
+            res
+        })();
+        status = report(res);
+    }
+
+    status
+}
+
+fn run_command(words: &[String], background: bool, stdio: &mut Stdio) -> anyhow::Result<()> {
+    // e.g. `$EMPTY` only
+    if words.is_empty() {
+        return Ok(());
+    }
+    let arg0 = std::iter::once("CMDLINE".to_string());
+    let parsed = CommandParser::try_parse_from(arg0.chain(words.iter().cloned()))?;
+    anyhow::ensure!(
+        !background || (matches!(parsed.command, Commands::Run { .. }) && !stdio.is_redirected()),
+        "&: only `run` can be run in the background (without redirections)"
+    );
 
     match parsed.command {
-        Commands::Pwd => cmd_pwd(),
+        Commands::Run { .. } | Commands::Fg { .. } | Commands::Bg { .. }
+            if stdio.is_redirected() =>
+        {
+            anyhow::bail!("{}: jobs cannot be redirected", words[0])
+        }
+        Commands::Pwd => cmd_pwd(stdio.out),
         Commands::Cd { dir } => cmd_cd(dir),
-        Commands::Ls { paths } => cmd_ls(&paths),
+        Commands::Ls { paths } => cmd_ls(&paths, stdio.out),
+        Commands::Cat { paths } => file::cat(&paths, stdio),
+        Commands::Echo { no_newline, args } => cmd_echo(&args, no_newline, stdio.out),
+        Commands::Grep {
+            ignore_case,
+            invert,
+            line_number,
+            count,
+            pattern,
+            paths,
+        } => {
+            let opts = text::GrepOptions {
+                ignore_case,
+                invert,
+                line_number,
+                count,
+            };
+            // 1 is "not found", so errors are 2 as POSIX
+            let found = text::grep(&pattern, &paths, &opts, stdio).map_err(|err| ExitStatus {
+                code: 2,
+                message: format!("{err:#}"),
+            })?;
+            exit_status(if found { 0 } else { 1 })
+        }
+        Commands::True => Ok(()),
+        Commands::False => exit_status(1),
+        Commands::Test { args } => cond::test("test", &args),
+        Commands::Bracket { args } => cond::test("[", &args),
+        Commands::Mkdir { parents, dirs } => file::mkdir(&dirs, parents),
+        Commands::Rm {
+            recursive,
+            force,
+            paths,
+        } => file::rm(&paths, recursive, force),
+        Commands::Mv { paths } => file::mv(&paths),
+        Commands::Cp { recursive, paths } => file::cp(&paths, recursive),
+        Commands::Touch { paths } => file::touch(&paths),
+        Commands::Mem => cmd_mem(stdio.out),
+        Commands::Lua { exec, script_args } => {
+            let (script, args) = match script_args.split_first() {
+                Some((script, args)) => (Some(script.as_str()), args),
+                None => (None, &[][..]),
+            };
+            cmd_lua(&exec, script, args, stdio)
+        }
+        Commands::Run { script_args } => {
+            let (script, args) = script_args.split_first().expect("required by clap");
+            cmd_run(&words.join(" "), script, args, background)
+        }
+        Commands::Sh { script_args } => {
+            let (script, args) = script_args.split_first().expect("required by clap");
+            script::run(script, args, true, stdio)
+        }
+        Commands::Source { script_args } => {
+            let (script, args) = script_args.split_first().expect("required by clap");
+            script::run(script, args, false, stdio)
+        }
+        Commands::Set { options } => script::set(&options, stdio.out),
+        Commands::Jobs => job::list(stdio.out),
+        Commands::Kill { id } => job::kill(id, stdio.out),
+        Commands::Fg { id } => job::foreground(id),
+        Commands::Bg { id } => job::background(id),
+        Commands::Game { op } => cmd_game(op, stdio.out),
+        Commands::Session { op } => cmd_session(op, stdio.out),
     }
 }
 
-fn cmd_pwd() -> anyhow::Result<()> {
+fn cmd_pwd(out: &mut dyn Write) -> anyhow::Result<()> {
     let dir = std::env::current_dir()?;
-    println!("{}", dir.as_os_str().to_string_lossy());
+    writeln!(out, "{}", dir.as_os_str().to_string_lossy())?;
 
     Ok(())
 }
@@ -55,13 +672,373 @@ fn cmd_cd(dir: Option<String>) -> anyhow::Result<()> {
     std::env::set_current_dir(dir).context("Change directory failed")
 }
 
-fn cmd_ls(paths: &Vec<String>) -> anyhow::Result<()> {
-    if paths.is_empty() {
-        super::fs::ls(".", false)?;
+/// One entry per line (directories end with '/').
+fn cmd_ls(paths: &[String], out: &mut dyn Write) -> anyhow::Result<()> {
+    let paths = if paths.is_empty() {
+        vec![".".to_string()]
+    } else {
+        paths.to_vec()
+    };
+
+    for (i, path) in paths.iter().enumerate() {
+        let p = std::path::Path::new(path);
+        anyhow::ensure!(
+            p.exists(),
+            "ls: cannot access '{path}': No such file or directory"
+        );
+        if !p.is_dir() {
+            writeln!(out, "{path}")?;
+            continue;
+        }
+
+        if paths.len() > 1 {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            writeln!(out, "{path}:")?;
+        }
+        let mut names: Vec<String> = super::fs::ls(path, false)?
+            .into_iter()
+            .map(|(name, ftype)| match ftype {
+                EntryType::DIR => format!("{}/", name.display()),
+                EntryType::FILE => name.display().to_string(),
+            })
+            .collect();
+        names.sort();
+        for name in names {
+            writeln!(out, "{name}")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_echo(args: &[String], no_newline: bool, out: &mut dyn Write) -> anyhow::Result<()> {
+    write!(out, "{}", args.join(" "))?;
+    if !no_newline {
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+fn cmd_mem(out: &mut dyn Write) -> anyhow::Result<()> {
+    let status = super::lua::status()?;
+    let limit = super::lua::config()
+        .memory_limit
+        .map_or("unlimited".to_string(), |bytes| format!("{bytes} B"));
+    writeln!(
+        out,
+        "lua: {} B (peak: {} B, limit: {limit})",
+        status.used_memory, status.peak_memory
+    )?;
+    writeln!(
+        out,
+        "wasm: {} B",
+        crate::emapi::emscripten::wasm_memory_size()
+    )?;
+
+    Ok(())
+}
+
+/// `print()` of Lua goes to `stdio.out` if it is redirected.
+fn cmd_lua(
+    exec: &[String],
+    script: Option<&str>,
+    args: &[String],
+    stdio: &mut Stdio,
+) -> anyhow::Result<()> {
+    if exec.is_empty() && script.is_none() {
+        anyhow::ensure!(!stdio.is_redirected(), "lua: REPL cannot be redirected");
+        repl::enter();
+        return Ok(());
+    }
+
+    let run = || -> anyhow::Result<()> {
+        let mut head = vec!["lua".to_string()];
+        for code in exec {
+            head.push("-e".to_string());
+            head.push(code.clone());
+        }
+        super::lua::set_arg(&head, script.unwrap_or_default(), args)?;
+
+        for code in exec {
+            super::lua::exec(code, "=(command line)")?;
+        }
+        if let Some(script) = script {
+            super::lua::exec_file(script, args)?;
+        }
+
+        Ok(())
+    };
+
+    let res = if stdio.is_terminal {
+        run()
+    } else {
+        let buf = Rc::new(RefCell::new(String::new()));
+        let res = super::lua::capture_print(&buf, run);
+        stdio.out.write_all(buf.borrow().as_bytes())?;
+        res
+    };
+    let status = match res {
+        Ok(()) => 0,
+        Err(err) => super::lua::exit_code(&err).unwrap_or_else(|| {
+            super::lua::report(&err);
+            1
+        }),
+    };
+
+    exit_status(status)
+}
+
+fn cmd_run(command: &str, script: &str, args: &[String], background: bool) -> anyhow::Result<()> {
+    let id = job::start(command, script, args, background)?;
+    if background {
+        println!("[{id}] {command}");
     }
-    for path in paths {
-        super::fs::ls(path, false)?;
+
+    Ok(())
+}
+
+fn cmd_game(op: GameOp, out: &mut dyn Write) -> anyhow::Result<()> {
+    match op {
+        GameOp::Status => {}
+        GameOp::Restart => {
+            anyhow::ensure!(super::game::start()?, "update() or draw() is not defined");
+        }
+        GameOp::Stop => super::game::stop(),
+    }
+    writeln!(out, "game: {}", super::game::status())?;
+
+    Ok(())
+}
+
+fn cmd_session(op: SessionOp, out: &mut dyn Write) -> anyhow::Result<()> {
+    match op {
+        SessionOp::Status => {
+            let status = super::lua::status()?;
+            writeln!(out, "uptime: {:.1} s", status.uptime_ms / 1000.0)?;
+            writeln!(out, "runs: {}", status.exec_count)?;
+            writeln!(out, "memory: {} B", status.used_memory)?;
+            writeln!(
+                out,
+                "libs: {}",
+                super::lua::lib_names(status.libs).join(" ")
+            )?;
+            match status.sandbox_root {
+                Some(root) => writeln!(out, "sandbox: {}", root.display()),
+                None => writeln!(out, "sandbox: (disabled)"),
+            }?;
+            writeln!(out, "globals: {}", status.user_globals.join(" "))?;
+            writeln!(out, "modules: {}", status.user_modules.join(" "))?;
+            writeln!(out, "tasks: {}", status.tasks)?;
+        }
+        SessionOp::Reset {
+            libs,
+            sandbox,
+            no_sandbox,
+        } => {
+            let libs = libs.as_deref().map(super::lua::parse_libs).transpose()?;
+            let sandbox = sandbox.map(std::path::absolute).transpose()?;
+            super::lua::update_config(|config| {
+                if let Some(libs) = libs {
+                    config.libs = libs;
+                }
+                if let Some(root) = sandbox {
+                    config.sandbox_root = Some(root);
+                }
+                if no_sandbox {
+                    config.sandbox_root = None;
+                }
+            })?;
+            // jobs are coroutines of the old state
+            job::clear();
+            super::lua::reset()?;
+            writeln!(out, "Lua session reset")?;
+        }
+        SessionOp::Limit {
+            instructions,
+            timeout,
+            memory,
+        } => {
+            super::lua::update_config(|config| {
+                if let Some(n) = instructions {
+                    config.limits.max_instructions = (n != 0).then_some(n);
+                }
+                if let Some(ms) = timeout {
+                    config.limits.timeout_ms = (ms != 0).then_some(ms as f64);
+                }
+                if let Some(bytes) = memory {
+                    config.memory_limit = (bytes != 0).then_some(bytes);
+                }
+            })?;
+            let config = super::lua::config();
+            let limits = config.limits;
+            match limits.max_instructions {
+                Some(n) => writeln!(out, "instructions: {n}"),
+                None => writeln!(out, "instructions: unlimited"),
+            }?;
+            match limits.timeout_ms {
+                Some(ms) => writeln!(out, "timeout: {ms} ms"),
+                None => writeln!(out, "timeout: unlimited"),
+            }?;
+            match config.memory_limit {
+                Some(bytes) => writeln!(out, "memory: {bytes} B"),
+                None => writeln!(out, "memory: unlimited"),
+            }?;
+        }
+        SessionOp::Path { op } => {
+            match op {
+                None => {}
+                Some(PathOp::Add { dir }) => {
+                    let dir = std::path::absolute(&dir)?;
+                    anyhow::ensure!(dir.is_dir(), "Not a directory: {}", dir.display());
+                    super::lua::update_config(|config| {
+                        if !config.search_roots.contains(&dir) {
+                            config.search_roots.push(dir);
+                        }
+                    })?;
+                }
+                Some(PathOp::Remove { dir }) => {
+                    let dir = std::path::absolute(&dir)?;
+                    super::lua::update_config(|config| {
+                        config.search_roots.retain(|root| *root != dir);
+                    })?;
+                }
+            }
+            for root in super::lua::config().module_roots() {
+                writeln!(out, "{}", root.display())?;
+            }
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The message of the error of `res` with its causes
+    /// (as the command line prints it).
+    pub(super) fn error<T: std::fmt::Debug>(res: anyhow::Result<T>) -> String {
+        format!("{:#}", res.unwrap_err())
+    }
+
+    /// Each pipeline as "<connector> <stages> <&>" (words not expanded).
+    fn parse(line: &str) -> anyhow::Result<Vec<String>> {
+        let list = parse_list(lexer::tokenize(line)?)?;
+        Ok(list
+            .iter()
+            .map(|pipeline| {
+                let connector = match pipeline.connector {
+                    Connector::Always => "",
+                    Connector::IfSuccess => "&& ",
+                    Connector::IfFailure => "|| ",
+                };
+                let stages: Vec<_> = pipeline
+                    .stages
+                    .iter()
+                    .map(|stage| {
+                        let mut res: Vec<_> = stage.words.iter().map(Word::to_string).collect();
+                        if let Some(path) = &stage.input {
+                            res.push(format!("<{path}"));
+                        }
+                        if let Some((path, append)) = &stage.output {
+                            res.push(format!("{}{path}", if *append { ">>" } else { ">" }));
+                        }
+                        res.join(" ")
+                    })
+                    .collect();
+                let background = if pipeline.background { " &" } else { "" };
+                format!("{connector}{}{background}", stages.join(" | "))
+            })
+            .collect())
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(parse("").unwrap(), [""; 0]);
+        assert_eq!(
+            parse("a; b && c || d &").unwrap(),
+            ["a", "b", "&& c", "|| d &"]
+        );
+        assert_eq!(parse("a & b").unwrap(), ["a &", "b"]);
+        assert_eq!(parse("a;").unwrap(), ["a"]);
+        assert_eq!(parse("a 'b;c' \"&&\"").unwrap(), ["a b;c &&"]);
+    }
+
+    #[test]
+    fn pipelines() {
+        assert_eq!(
+            parse("cat < in | grep x >> log && wc>out").unwrap(),
+            ["cat <in | grep x >>log", "&& wc >out"]
+        );
+        // a redirection only
+        assert_eq!(parse("> file").unwrap(), [">file"]);
+    }
+
+    /// `$?` after running `line`.
+    fn status(line: &str) -> i32 {
+        run_line(line, &mut Vec::new(), false).unwrap();
+        STATUS.get()
+    }
+
+    #[test]
+    fn statuses() {
+        assert_eq!(status("true"), 0);
+        assert_eq!(status("false"), 1);
+        assert_eq!(status("false || true"), 0);
+        assert_eq!(status("true && false"), 1);
+        assert_eq!(status("test 1 -eq"), 2);
+        assert_eq!(status("echo abc | grep b"), 0);
+        assert_eq!(status("echo abc | grep x"), 1);
+        assert_eq!(status("grep x /nonexistent"), 2);
+        assert_eq!(status("echo abc | grep b - /nonexistent"), 2);
+    }
+
+    #[test]
+    fn script_params() {
+        let dir = std::env::temp_dir().join("rustlua-script-params");
+        std::fs::create_dir_all(&dir).unwrap();
+        let inner = dir.join("inner.sh");
+        let outer = dir.join("outer.sh");
+        std::fs::write(&inner, "echo $#\n").unwrap();
+        std::fs::write(&outer, format!("sh {} \"$@\"\n", inner.display())).unwrap();
+
+        let count = |args: &str| {
+            let mut out = Vec::new();
+            run_line(&format!("sh {} {args}", outer.display()), &mut out, false).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(count("a b"), "2\n");
+        assert_eq!(count("'a b'"), "1\n");
+        assert_eq!(count(""), "0\n");
+
+        // `source` without arguments keeps those of the caller
+        std::fs::write(&outer, format!("source {}\n", inner.display())).unwrap();
+        assert_eq!(count("a b"), "2\n");
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let error = |line| error(parse(line));
+        assert_eq!(error("; a"), "syntax error near unexpected token ';'");
+        assert_eq!(
+            error("a && && b"),
+            "syntax error near unexpected token '&&'"
+        );
+        assert_eq!(error("a & && b"), "syntax error near unexpected token '&&'");
+        assert_eq!(error("a &&"), "syntax error: unexpected end of line");
+        assert_eq!(error("a ||"), "syntax error: unexpected end of line");
+        assert_eq!(error("| a"), "syntax error near unexpected token '|'");
+        assert_eq!(error("a | | b"), "syntax error near unexpected token '|'");
+        assert_eq!(
+            error("a |"),
+            "syntax error: unexpected end of line after '|'"
+        );
+        assert_eq!(error("a >"), "syntax error near unexpected token 'newline'");
+        assert_eq!(error("a > | b"), "syntax error near unexpected token '|'");
+        assert_eq!(error("a < > b"), "syntax error near unexpected token '>'");
+    }
+}
@@ -0,0 +1,147 @@
+//! `test` and `[`: conditional expressions.
+//!
+//! Expressions are evaluated by the number of arguments as POSIX does.
+//! `-a`, `-o` and parentheses for grouping more than one expression are
+//! not supported. Like [crate::app::fs], permissions are not considered.
+
+use std::path::Path;
+
+use super::{ExitStatus, exit_status};
+
+fn unary(op: &str, arg: &str) -> Result<bool, String> {
+    let path = Path::new(arg);
+    Ok(match op {
+        "-e" => path.exists(),
+        "-f" => path.is_file(),
+        "-d" => path.is_dir(),
+        "-s" => std::fs::metadata(path).is_ok_and(|meta| meta.len() > 0),
+        "-n" => !arg.is_empty(),
+        "-z" => arg.is_empty(),
+        _ => return Err(format!("{op}: unary operator expected")),
+    })
+}
+
+fn is_binary(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "==" | "!=" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge"
+    )
+}
+
+fn integer(arg: &str) -> Result<i64, String> {
+    arg.trim()
+        .parse()
+        .map_err(|_| format!("{arg}: integer expression expected"))
+}
+
+fn binary(lhs: &str, op: &str, rhs: &str) -> Result<bool, String> {
+    Ok(match op {
+        "=" | "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        _ => {
+            let (lhs, rhs) = (integer(lhs)?, integer(rhs)?);
+            match op {
+                "-eq" => lhs == rhs,
+                "-ne" => lhs != rhs,
+                "-lt" => lhs < rhs,
+                "-le" => lhs <= rhs,
+                "-gt" => lhs > rhs,
+                _ => lhs >= rhs,
+            }
+        }
+    })
+}
+
+fn eval(args: &[&str]) -> Result<bool, String> {
+    match *args {
+        [] => Ok(false),
+        [arg] => Ok(!arg.is_empty()),
+        [lhs, op, rhs] if is_binary(op) => binary(lhs, op, rhs),
+        ["!", ref rest @ ..] if rest.len() <= 3 => eval(rest).map(|b| !b),
+        [op, arg] => unary(op, arg),
+        ["(", ref inner @ .., ")"] if inner.len() <= 2 => eval(inner),
+        [_, op, _] => Err(format!("{op}: binary operator expected")),
+        _ => Err("too many arguments".to_string()),
+    }
+}
+
+/// `name` is "test" or "[".
+pub fn test(name: &str, args: &[String]) -> anyhow::Result<()> {
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if name == "[" {
+        if args.last() != Some(&"]") {
+            return Err(ExitStatus {
+                code: 2,
+                message: "[: missing ']'".to_string(),
+            }
+            .into());
+        }
+        args.pop();
+    }
+
+    match eval(&args) {
+        Ok(res) => exit_status(if res { 0 } else { 1 }),
+        Err(msg) => Err(ExitStatus {
+            code: 2,
+            message: format!("{name}: {msg}"),
+        }
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn by_argument_count() {
+        assert_eq!(eval(&[]), Ok(false));
+        assert_eq!(eval(&["x"]), Ok(true));
+        assert_eq!(eval(&[""]), Ok(false));
+        // a single argument is a string, not an operator
+        assert_eq!(eval(&["-n"]), Ok(true));
+        assert_eq!(eval(&["-z"]), Ok(true));
+        assert_eq!(eval(&["!"]), Ok(true));
+        assert_eq!(eval(&["-n", ""]), Ok(false));
+        assert_eq!(eval(&["-z", ""]), Ok(true));
+        // the binary operator wins with three arguments
+        assert_eq!(eval(&["=", "=", "="]), Ok(true));
+        assert_eq!(eval(&["!", "=", "!"]), Ok(true));
+        assert_eq!(eval(&["(", "x", ")"]), Ok(true));
+        assert_eq!(eval(&["(", "", ")"]), Ok(false));
+        assert_eq!(eval(&["(", "-n", "", ")"]), Ok(false));
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(eval(&["!", ""]), Ok(true));
+        assert_eq!(eval(&["!", "a", "=", "b"]), Ok(true));
+        assert_eq!(eval(&["!", "a", "=", "a"]), Ok(false));
+        assert_eq!(eval(&["!", "!", "a"]), Ok(true));
+    }
+
+    #[test]
+    fn binary() {
+        assert_eq!(eval(&["a", "==", "a"]), Ok(true));
+        assert_eq!(eval(&["a", "!=", "a"]), Ok(false));
+        assert_eq!(eval(&["1", "-lt", "2"]), Ok(true));
+        assert_eq!(eval(&[" 10 ", "-ge", "9"]), Ok(true));
+        assert_eq!(eval(&["-1", "-gt", "1"]), Ok(false));
+    }
+
+    #[test]
+    fn errors() {
+        let err = |msg: &str| Err(msg.to_string());
+        assert_eq!(eval(&["-x", "a"]), err("-x: unary operator expected"));
+        assert_eq!(eval(&["a", "b", "c"]), err("b: binary operator expected"));
+        assert_eq!(
+            eval(&["a", "-eq", "1"]),
+            err("a: integer expression expected")
+        );
+        assert_eq!(eval(&["a", "b", "c", "d"]), err("too many arguments"));
+        assert_eq!(
+            eval(&["!", "a", "b", "c"]),
+            err("b: binary operator expected")
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! File manipulation commands (`cat`, `mkdir`, `rm`, `mv`, `cp`, `touch`).
+//!
+//! Flags and error messages follow POSIX (GNU coreutils) where it makes
+//! sense. Like [crate::app::fs], only regular files and directories are
+//! considered. An error on one operand does not stop the others;
+//! all of them are reported at the end.
+
+use std::path::{Path, PathBuf};
+
+use crate::app::fs::{self, EntryType};
+
+use super::Stdio;
+
+/// Error message without "(os error N)".
+pub fn io_msg(err: &std::io::Error) -> String {
+    use std::io::ErrorKind;
+
+    match err.kind() {
+        ErrorKind::NotFound => "No such file or directory".to_string(),
+        ErrorKind::AlreadyExists => "File exists".to_string(),
+        ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        ErrorKind::IsADirectory => "Is a directory".to_string(),
+        ErrorKind::NotADirectory => "Not a directory".to_string(),
+        ErrorKind::DirectoryNotEmpty => "Directory not empty".to_string(),
+        _ => err.to_string(),
+    }
+}
+
+/// Run `f` for each operand and report all errors at once.
+pub fn for_each<T>(items: &[T], mut f: impl FnMut(&T) -> Result<(), String>) -> anyhow::Result<()> {
+    let errors: Vec<String> = items.iter().filter_map(|item| f(item).err()).collect();
+    anyhow::ensure!(errors.is_empty(), "{}", errors.join("\n"));
+
+    Ok(())
+}
+
+/// `true` if `path` is `dir` or inside of it.
+fn is_inside(path: &Path, dir: &Path) -> bool {
+    match (std::path::absolute(path), std::path::absolute(dir)) {
+        (Ok(path), Ok(dir)) => fs::normalize(&path).starts_with(fs::normalize(&dir)),
+        _ => false,
+    }
+}
+
+/// `true` if `a` and `b` are the same path (after normalization).
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::path::absolute(a), std::path::absolute(b)) {
+        (Ok(a), Ok(b)) => fs::normalize(&a) == fs::normalize(&b),
+        _ => false,
+    }
+}
+
+/// `dst/<name of src>` if `dst` is a directory, otherwise `dst`.
+fn target(src: &Path, dst: &Path) -> Option<PathBuf> {
+    if dst.is_dir() {
+        src.file_name().map(|name| dst.join(name))
+    } else {
+        Some(dst.to_path_buf())
+    }
+}
+
+/// Split `paths` into the sources and the destination.
+/// Several sources need a destination directory.
+fn split_dest<'a>(cmd: &str, paths: &'a [String]) -> anyhow::Result<(&'a [String], &'a Path)> {
+    let (dst, srcs) = match paths.split_last() {
+        Some((dst, srcs)) if !srcs.is_empty() => (Path::new(dst), srcs),
+        Some((dst, _)) => anyhow::bail!("{cmd}: missing destination file operand after '{dst}'"),
+        None => anyhow::bail!("{cmd}: missing file operand"),
+    };
+    anyhow::ensure!(
+        srcs.len() == 1 || dst.is_dir(),
+        "{cmd}: target '{}' is not a directory",
+        dst.display()
+    );
+
+    Ok((srcs, dst))
+}
+
+/// Read `path`, or the input of the command if it is "-".
+pub fn read_input(cmd: &str, path: &str, stdio: &mut Stdio) -> Result<Vec<u8>, String> {
+    if path == "-" {
+        return stdio
+            .input
+            .take()
+            .ok_or_else(|| format!("{cmd}: no input (use '<' or '|')"));
+    }
+    if Path::new(path).is_dir() {
+        return Err(format!("{cmd}: {path}: Is a directory"));
+    }
+
+    std::fs::read(path).map_err(|e| format!("{cmd}: {path}: {}", io_msg(&e)))
+}
+
+pub fn cat(paths: &[String], stdio: &mut Stdio) -> anyhow::Result<()> {
+    let stdin = ["-".to_string()];
+    let paths = if paths.is_empty() { &stdin[..] } else { paths };
+
+    for_each(paths, |path| {
+        let data = read_input("cat", path, stdio)?;
+        stdio
+            .out
+            .write_all(&data)
+            .map_err(|e| format!("cat: write error: {}", io_msg(&e)))
+    })
+}
+
+pub fn mkdir(dirs: &[String], parents: bool) -> anyhow::Result<()> {
+    for_each(dirs, |dir| {
+        let res = if parents {
+            std::fs::create_dir_all(dir)
+        } else {
+            std::fs::create_dir(dir)
+        };
+        res.map_err(|e| format!("mkdir: cannot create directory '{dir}': {}", io_msg(&e)))
+    })
+}
+
+pub fn rm(paths: &[String], recursive: bool, force: bool) -> anyhow::Result<()> {
+    for_each(paths, |path| {
+        let p = Path::new(path);
+        if recursive && p.parent().is_none() && p.has_root() {
+            return Err(format!(
+                "rm: it is dangerous to operate recursively on '{path}'"
+            ));
+        }
+        if p.file_name().is_none() || path.ends_with("/.") {
+            return Err(format!(
+                "rm: refusing to remove '.' or '..' directory: skipping '{path}'"
+            ));
+        }
+        let err = |e: std::io::Error| format!("rm: cannot remove '{path}': {}", io_msg(&e));
+
+        match std::fs::metadata(p) {
+            Err(e) if force && e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(err(e)),
+            Ok(meta) if meta.is_dir() => {
+                if !recursive {
+                    return Err(format!("rm: cannot remove '{path}': Is a directory"));
+                }
+                remove_tree(p).map_err(err)
+            }
+            Ok(_) => std::fs::remove_file(p).map_err(err),
+        }
+    })
+}
+
+/// Remove files first, then directories from the deepest one.
+fn remove_tree(dir: &Path) -> std::io::Result<()> {
+    let mut entries = fs::ls_recursive(dir, false).map_err(std::io::Error::other)?;
+    entries.sort_by_key(|(path, _)| std::cmp::Reverse(path.components().count()));
+    for (path, ftype) in &entries {
+        match ftype {
+            EntryType::FILE => std::fs::remove_file(dir.join(path))?,
+            EntryType::DIR => std::fs::remove_dir(dir.join(path))?,
+        }
+    }
+
+    std::fs::remove_dir(dir)
+}
+
+pub fn mv(paths: &[String]) -> anyhow::Result<()> {
+    let (srcs, dst) = split_dest("mv", paths)?;
+
+    for_each(srcs, |src| {
+        let src_path = Path::new(src);
+        let to = target(src_path, dst)
+            .ok_or_else(|| format!("mv: cannot move '{src}': Invalid argument"))?;
+        if !src_path.exists() {
+            return Err(format!(
+                "mv: cannot stat '{src}': No such file or directory"
+            ));
+        }
+        if src_path.is_dir() && is_inside(&to, src_path) {
+            return Err(format!(
+                "mv: cannot move '{src}' to a subdirectory of itself, '{}'",
+                to.display()
+            ));
+        }
+        std::fs::rename(src_path, &to).map_err(|e| {
+            format!(
+                "mv: cannot move '{src}' to '{}': {}",
+                to.display(),
+                io_msg(&e)
+            )
+        })
+    })
+}
+
+pub fn cp(paths: &[String], recursive: bool) -> anyhow::Result<()> {
+    let (srcs, dst) = split_dest("cp", paths)?;
+
+    for_each(srcs, |src| {
+        let src_path = Path::new(src);
+        let to = target(src_path, dst)
+            .ok_or_else(|| format!("cp: cannot copy '{src}': Invalid argument"))?;
+        let meta = std::fs::metadata(src_path)
+            .map_err(|e| format!("cp: cannot stat '{src}': {}", io_msg(&e)))?;
+
+        if !meta.is_dir() {
+            return copy_file(src_path, &to);
+        }
+        if !recursive {
+            return Err(format!("cp: -r not specified; omitting directory '{src}'"));
+        }
+        if is_inside(&to, src_path) {
+            return Err(format!(
+                "cp: cannot copy a directory, '{src}', into itself, '{}'",
+                to.display()
+            ));
+        }
+        copy_tree(src_path, &to)
+    })
+}
+
+/// `std::fs::copy` truncates `dst` first, so copying a file onto itself
+/// would empty it.
+fn copy_file(src: &Path, dst: &Path) -> Result<(), String> {
+    if same_file(src, dst) {
+        return Err(format!(
+            "cp: '{}' and '{}' are the same file",
+            src.display(),
+            dst.display()
+        ));
+    }
+
+    std::fs::copy(src, dst).map(|_| ()).map_err(|e| {
+        format!(
+            "cp: cannot create regular file '{}': {}",
+            dst.display(),
+            io_msg(&e)
+        )
+    })
+}
+
+/// Copy the contents of `src` into `dst` (created if needed).
+fn copy_tree(src: &Path, dst: &Path) -> Result<(), String> {
+    let err = |e: std::io::Error| {
+        format!(
+            "cp: cannot copy '{}' to '{}': {}",
+            src.display(),
+            dst.display(),
+            io_msg(&e)
+        )
+    };
+    let mut entries = fs::ls_recursive(src, false)
+        .map_err(std::io::Error::other)
+        .map_err(err)?;
+    // parents first
+    entries.sort_by_key(|(path, _)| path.components().count());
+
+    std::fs::create_dir_all(dst).map_err(err)?;
+    for (path, ftype) in &entries {
+        match ftype {
+            EntryType::DIR => std::fs::create_dir_all(dst.join(path)).map_err(err)?,
+            EntryType::FILE => copy_file(&src.join(path), &dst.join(path))?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn touch(paths: &[String]) -> anyhow::Result<()> {
+    for_each(paths, |path| {
+        let err = |e: std::io::Error| format!("touch: cannot touch '{path}': {}", io_msg(&e));
+        if Path::new(path).is_dir() {
+            return Ok(());
+        }
+
+        let file = std::fs::File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(err)?;
+        file.set_modified(std::time::SystemTime::now()).map_err(err)
+    })
+}
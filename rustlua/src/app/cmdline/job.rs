@@ -0,0 +1,357 @@
+//! Job control for long-running Lua scripts.
+//!
+//! A job is a Lua script running in a coroutine.
+//! [step()] resumes each job once per frame from the main loop and the
+//! job is made to yield when its time slice is used up, so the page
+//! (and the shell) keeps working while e.g. a simulation is running.
+//! `wait()` and `sleep()` also work in a job.
+//!
+//! The output of `print()` of a background job is buffered until
+//! `fg` attaches to it. The foreground job prints directly and
+//! receives the command line as the input of `io.read()` while waiting
+//! for it (prefix a line with '!' to run it as a command instead).
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::app::lua::{self, sched};
+use crate::emapi;
+
+/// Time given to each job per frame (ms).
+const SLICE_MS: f64 = 4.0;
+/// Max size of the buffered output of a background job (bytes).
+const MAX_OUTPUT: usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Running,
+    Done(i32),
+    Failed,
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Running => write!(f, "running"),
+            Self::Done(0) => write!(f, "done"),
+            Self::Done(code) => write!(f, "exit {code}"),
+            Self::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+struct Job {
+    id: u32,
+    command: String,
+    thread: mlua::Thread,
+    script: String,
+    /// Arguments of the first resume
+    args: Option<Vec<String>>,
+    wake: sched::Wake,
+    state: State,
+    cpu_ms: f64,
+    output: Rc<RefCell<String>>,
+}
+
+impl Job {
+    fn step(&mut self, foreground: bool, now: f64) {
+        // only the foreground job reads the command line
+        let reading = matches!(self.wake, sched::Wake::Input);
+        if self.state != State::Running || (reading && !foreground) || !self.wake.is_due(now) {
+            return;
+        }
+
+        // `arg` is shared by the scripts, so set it just before the job starts
+        let args = self.args.take();
+        if let Some(args) = &args
+            && let Err(err) = lua::set_arg(&["run".to_string()], &self.script, args)
+        {
+            lua::report(&err);
+            self.state = State::Failed;
+            return;
+        }
+        let args: mlua::Variadic<String> = args.unwrap_or_default().into_iter().collect();
+        let started = emapi::emscripten::performance_now();
+        let res = if foreground {
+            lua::resume_slice(&self.thread, args, SLICE_MS)
+        } else {
+            lua::capture_print(&self.output, || {
+                lua::resume_slice(&self.thread, args, SLICE_MS)
+            })
+        };
+        self.cpu_ms += emapi::emscripten::performance_now() - started;
+
+        match res {
+            Ok(values) => {
+                if self.thread.status() == mlua::ThreadStatus::Resumable {
+                    if let Ok(lua) = lua::lua() {
+                        self.wake = sched::next_wake(&lua, &values, now);
+                    }
+                } else {
+                    self.state = State::Done(0);
+                }
+            }
+            Err(err) => {
+                self.state = match lua::exit_code(&err) {
+                    Some(code) => State::Done(code),
+                    None => {
+                        lua::report(&err);
+                        State::Failed
+                    }
+                };
+            }
+        }
+
+        // keep the tail
+        let mut output = self.output.borrow_mut();
+        if output.len() > MAX_OUTPUT {
+            let mut cut = output.len() - MAX_OUTPUT;
+            while !output.is_char_boundary(cut) {
+                cut += 1;
+            }
+            output.drain(..cut);
+        }
+    }
+
+    /// Print and clear the buffered output.
+    fn flush(&self) {
+        let output = self.output.take();
+        print!("{output}");
+    }
+}
+
+struct Jobs {
+    list: Vec<Job>,
+    next_id: u32,
+    foreground: Option<u32>,
+}
+
+impl Jobs {
+    /// `true` if job `id` is in the foreground and waiting for input.
+    fn is_reading(&self, id: u32) -> bool {
+        self.foreground == Some(id)
+            && self.list.iter().any(|job| {
+                job.id == id
+                    && job.state == State::Running
+                    && matches!(job.wake, sched::Wake::Input)
+            })
+    }
+}
+
+thread_local! {
+    static JOBS: RefCell<Jobs> = const {
+        RefCell::new(Jobs {
+            list: Vec::new(),
+            next_id: 1,
+            foreground: None,
+        })
+    };
+}
+
+/// Start `script` as a job and return its id.
+pub fn start(
+    command: &str,
+    script: &str,
+    args: &[String],
+    background: bool,
+) -> anyhow::Result<u32> {
+    let func = lua::load_file(script)?;
+    let thread = lua::lua()?.create_thread(func)?;
+
+    let id = JOBS.with(|cell| {
+        let mut jobs = cell.borrow_mut();
+        let id = jobs.next_id;
+        jobs.next_id += 1;
+        jobs.list.push(Job {
+            id,
+            command: command.to_string(),
+            thread,
+            script: script.to_string(),
+            args: Some(args.to_vec()),
+            wake: sched::Wake::Frames(0),
+            state: State::Running,
+            cpu_ms: 0.0,
+            output: Rc::new(RefCell::new(String::new())),
+        });
+        if !background {
+            jobs.foreground = Some(id);
+        }
+
+        id
+    });
+
+    Ok(id)
+}
+
+/// Resume the jobs. Called every frame from the main loop.
+pub fn step() {
+    // taken out while Lua code runs
+    let (mut list, foreground) = JOBS.with(|cell| {
+        let mut jobs = cell.borrow_mut();
+        (std::mem::take(&mut jobs.list), jobs.foreground)
+    });
+    if list.is_empty() {
+        return;
+    }
+
+    let now = emapi::emscripten::performance_now();
+    for job in &mut list {
+        let was_running = job.state == State::Running;
+        let is_foreground = foreground == Some(job.id);
+        job.step(is_foreground, now);
+        if was_running && job.state != State::Running {
+            if is_foreground {
+                job.flush();
+            }
+            println!("[{}] {} {}", job.id, job.state, job.command);
+        }
+    }
+
+    JOBS.with(|cell| {
+        let mut jobs = cell.borrow_mut();
+        list.append(&mut jobs.list);
+        jobs.list = list;
+        // finished foreground jobs are not listed by `jobs`
+        let foreground = jobs.foreground;
+        jobs.list
+            .retain(|job| job.state == State::Running || Some(job.id) != foreground);
+        if let Some(id) = foreground
+            && !jobs.list.iter().any(|job| job.id == id)
+        {
+            jobs.foreground = None;
+        }
+    });
+}
+
+/// Print the jobs. Finished jobs are removed after being listed.
+pub fn list(out: &mut dyn Write) -> anyhow::Result<()> {
+    JOBS.with(|cell| {
+        let mut jobs = cell.borrow_mut();
+        for job in &jobs.list {
+            let mark = if jobs.foreground == Some(job.id) {
+                '+'
+            } else {
+                ' '
+            };
+            writeln!(
+                out,
+                "[{}]{mark} {:<8} {:>8.3}s  {}",
+                job.id,
+                job.state.to_string(),
+                job.cpu_ms / 1000.0,
+                job.command
+            )?;
+        }
+        jobs.list.retain(|job| job.state == State::Running);
+
+        Ok(())
+    })
+}
+
+/// Stop a job.
+pub fn kill(id: u32, out: &mut dyn Write) -> anyhow::Result<()> {
+    let (job, was_reading) = JOBS.with(|cell| {
+        let mut jobs = cell.borrow_mut();
+        let was_reading = jobs.is_reading(id);
+        let pos = jobs.list.iter().position(|job| job.id == id);
+        if jobs.foreground == Some(id) {
+            jobs.foreground = None;
+        }
+        (pos.map(|pos| jobs.list.remove(pos)), was_reading)
+    });
+    let job = job.ok_or_else(|| anyhow::anyhow!("kill: {id}: no such job"))?;
+    writeln!(out, "[{}] killed {}", job.id, job.command)?;
+    // drop the coroutine outside of the borrow
+    drop(job);
+    // the input was for the killed job (unless a task is also reading)
+    if was_reading && !sched::is_reading() {
+        lua::stdin::clear();
+    }
+
+    Ok(())
+}
+
+/// `true` if the foreground job or a task of `spawn()` is waiting for
+/// input of `io.read()`.
+pub fn is_reading() -> bool {
+    let job_reading = JOBS.with(|cell| {
+        let jobs = cell.borrow();
+        jobs.foreground.is_some_and(|id| jobs.is_reading(id))
+    });
+
+    job_reading || sched::is_reading()
+}
+
+/// Pass a line of the command line to the reader (see [is_reading()]).
+pub fn input(line: &str) {
+    println!("{line}");
+    lua::stdin::push_line(line);
+}
+
+/// Remove all jobs (the Lua state is being reset).
+pub fn clear() {
+    let list = JOBS.with(|cell| {
+        let mut jobs = cell.borrow_mut();
+        jobs.foreground = None;
+        std::mem::take(&mut jobs.list)
+    });
+    for job in &list {
+        println!("[{}] killed {}", job.id, job.command);
+    }
+    lua::stdin::clear();
+}
+
+/// `id` or the most recent job.
+fn find_id(jobs: &Jobs, cmd: &str, id: Option<u32>) -> anyhow::Result<u32> {
+    let found = match id {
+        Some(id) => jobs.list.iter().find(|job| job.id == id),
+        None => jobs.list.last(),
+    };
+
+    match (found, id) {
+        (Some(job), _) => Ok(job.id),
+        (None, Some(id)) => anyhow::bail!("{cmd}: {id}: no such job"),
+        (None, None) => anyhow::bail!("{cmd}: no current job"),
+    }
+}
+
+/// Attach to the output of a job.
+pub fn foreground(id: Option<u32>) -> anyhow::Result<()> {
+    JOBS.with(|cell| {
+        let mut jobs = cell.borrow_mut();
+        let id = find_id(&jobs, "fg", id)?;
+        let pos = jobs.list.iter().position(|job| job.id == id).unwrap();
+
+        let job = &jobs.list[pos];
+        println!("{}", job.command);
+        job.flush();
+        if job.state == State::Running {
+            jobs.foreground = Some(id);
+        } else {
+            println!("[{}] {} {}", job.id, job.state, job.command);
+            jobs.list.remove(pos);
+        }
+
+        Ok(())
+    })
+}
+
+/// Detach from the output of a job.
+pub fn background(id: Option<u32>) -> anyhow::Result<()> {
+    JOBS.with(|cell| {
+        let mut jobs = cell.borrow_mut();
+        let id = match id {
+            Some(id) => find_id(&jobs, "bg", Some(id))?,
+            None => jobs
+                .foreground
+                .ok_or_else(|| anyhow::anyhow!("bg: no current job"))?,
+        };
+        if jobs.foreground == Some(id) {
+            jobs.foreground = None;
+        }
+        println!("[{id}] running in background");
+
+        Ok(())
+    })
+}
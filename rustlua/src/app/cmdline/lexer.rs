@@ -0,0 +1,463 @@
+//! Tokenizer of the command line (a small subset of the POSIX shell).
+//!
+//! * `'...'`: literal
+//! * `"..."`: `$VAR`, `${VAR}` and `\` before `\ " $` are processed
+//! * `\c` (outside quotes): literal `c`
+//! * `$VAR`, `${VAR}`: environment variable (empty if not set);
+//!   the value is not split into words
+//! * `$?`: exit status of the last command
+//! * `$0`..`$9`, `$#`, `$@`, `$*`: parameters of the running script
+//!   (see [super::script]); `${10}` for more.
+//!   `$@` (also in `"..."`) is one word per parameter
+//! * `#` at the start of a word: comment until the end of the line
+//! * `~`, `~/...` at the start of a word: [HOME_DIR]
+//! * `&` (unquoted): run in the background
+//! * `|`, `<`, `>`, `>>` (unquoted): pipe and redirections
+//! * `;`, `&&`, `||` (unquoted): command list
+//!
+//! Variables are expanded by [Word::expand()] just before each command
+//! runs, so that `false; echo $?` sees the status of `false`.
+
+use crate::app::fs::HOME_DIR;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    /// Name of a variable
+    Var(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Word {
+    parts: Vec<Part>,
+    /// `true` if any part is quoted (`""` is an empty word)
+    quoted: bool,
+}
+
+impl Word {
+    /// `lookup` returns the values of a variable (one for each parameter
+    /// for `$@`). Values after the first one start new words.
+    ///
+    /// The word disappears if it is unquoted and empty after expansion,
+    /// or only `$@` without parameters (`"$@"` too).
+    pub fn expand(&self, lookup: impl Fn(&str) -> Vec<String>) -> Vec<String> {
+        let mut words = vec![String::new()];
+        let mut no_params = false;
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => words.last_mut().unwrap().push_str(text),
+                Part::Var(name) => {
+                    let mut values = lookup(name).into_iter();
+                    match values.next() {
+                        Some(first) => words.last_mut().unwrap().push_str(&first),
+                        None => no_params = true,
+                    }
+                    words.extend(values);
+                }
+            }
+        }
+
+        let only_params = matches!(&self.parts[..], [Part::Var(name)] if name == "@");
+        if words == [""] && (!self.quoted || (only_params && no_params)) {
+            words.clear();
+        }
+
+        words
+    }
+}
+
+impl std::fmt::Display for Word {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => write!(f, "{text}")?,
+                Part::Var(name) => write!(f, "${{{name}}}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    /// `&`
+    Background,
+    /// `|`
+    Pipe,
+    /// `<`
+    RedirectIn,
+    /// `>`
+    RedirectOut,
+    /// `>>`
+    Append,
+    /// `;`
+    Semicolon,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "{word}"),
+            Self::Background => write!(f, "&"),
+            Self::Pipe => write!(f, "|"),
+            Self::RedirectIn => write!(f, "<"),
+            Self::RedirectOut => write!(f, ">"),
+            Self::Append => write!(f, ">>"),
+            Self::Semicolon => write!(f, ";"),
+            Self::And => write!(f, "&&"),
+            Self::Or => write!(f, "||"),
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Characters which end a word.
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '&' | '|' | '<' | '>' | ';')
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    tokens: Vec<Token>,
+    word: Word,
+    /// Text not yet pushed to `word`
+    text: String,
+    /// `true` if the current word has any character, variable or quotes.
+    in_word: bool,
+    /// `true` if the line ends in a comment.
+    comment: bool,
+}
+
+impl Lexer<'_> {
+    fn push_text(&mut self) {
+        if !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            self.word.parts.push(Part::Text(text));
+        }
+    }
+
+    fn push_var(&mut self, name: String) {
+        self.push_text();
+        self.word.parts.push(Part::Var(name));
+    }
+
+    fn end_word(&mut self) {
+        if self.in_word {
+            self.push_text();
+            self.tokens
+                .push(Token::Word(std::mem::take(&mut self.word)));
+            self.in_word = false;
+        }
+    }
+
+    /// After '$'.
+    fn variable(&mut self) -> anyhow::Result<()> {
+        match self.chars.peek() {
+            Some('{') => {
+                self.chars.next();
+                let mut name = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('}') => break,
+                        Some(c) if is_name_char(c) => name.push(c),
+                        Some(c) => {
+                            anyhow::bail!("syntax error: bad substitution '{c}' in ${{...}}")
+                        }
+                        None => anyhow::bail!("syntax error: unterminated ${{...}}"),
+                    }
+                }
+                anyhow::ensure!(!name.is_empty(), "syntax error: bad substitution ${{}}");
+                self.push_var(name);
+            }
+            Some(&c) if matches!(c, '?' | '#' | '@' | '*') || c.is_ascii_digit() => {
+                self.chars.next();
+                self.push_var(c.to_string());
+            }
+            Some(&c) if is_name_char(c) => {
+                let mut name = String::new();
+                while let Some(c) = self.chars.next_if(|&c| is_name_char(c)) {
+                    name.push(c);
+                }
+                self.push_var(name);
+            }
+            // not a variable
+            _ => self.text.push('$'),
+        }
+
+        Ok(())
+    }
+
+    fn single_quoted(&mut self) -> anyhow::Result<()> {
+        loop {
+            match self.chars.next() {
+                Some('\'') => return Ok(()),
+                Some(c) => self.text.push(c),
+                None => anyhow::bail!("syntax error: unterminated single quote"),
+            }
+        }
+    }
+
+    fn double_quoted(&mut self) -> anyhow::Result<()> {
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(()),
+                Some('\\') => match self.chars.next() {
+                    Some(c @ ('\\' | '"' | '$' | '`')) => self.text.push(c),
+                    Some(c) => {
+                        self.text.push('\\');
+                        self.text.push(c);
+                    }
+                    None => anyhow::bail!("syntax error: unterminated double quote"),
+                },
+                Some('$') => self.variable()?,
+                Some(c) => self.text.push(c),
+                None => anyhow::bail!("syntax error: unterminated double quote"),
+            }
+        }
+    }
+
+    /// The operator starting with `c`.
+    fn operator(&mut self, c: char) -> Token {
+        match c {
+            '&' if self.chars.next_if_eq(&'&').is_some() => Token::And,
+            '&' => Token::Background,
+            '|' if self.chars.next_if_eq(&'|').is_some() => Token::Or,
+            '|' => Token::Pipe,
+            '<' => Token::RedirectIn,
+            '>' if self.chars.next_if_eq(&'>').is_some() => Token::Append,
+            '>' => Token::RedirectOut,
+            _ => Token::Semicolon,
+        }
+    }
+
+    fn new(line: &str) -> Lexer<'_> {
+        Lexer {
+            chars: line.chars().peekable(),
+            tokens: Vec::new(),
+            word: Word::default(),
+            text: String::new(),
+            in_word: false,
+            comment: false,
+        }
+    }
+
+    fn run(&mut self) -> anyhow::Result<()> {
+        while let Some(c) = self.chars.next() {
+            match c {
+                c if c.is_whitespace() => self.end_word(),
+                '#' if !self.in_word => {
+                    self.comment = true;
+                    break;
+                }
+                '&' | '|' | '<' | '>' | ';' => {
+                    self.end_word();
+                    let token = self.operator(c);
+                    self.tokens.push(token);
+                }
+                '\'' => {
+                    self.in_word = true;
+                    self.word.quoted = true;
+                    self.single_quoted()?;
+                }
+                '"' => {
+                    self.in_word = true;
+                    self.word.quoted = true;
+                    self.double_quoted()?;
+                }
+                '\\' => match self.chars.next() {
+                    Some(c) => {
+                        self.in_word = true;
+                        self.text.push(c);
+                    }
+                    None => anyhow::bail!("syntax error: unexpected end of line after '\\'"),
+                },
+                '$' => {
+                    self.in_word = true;
+                    self.variable()?;
+                }
+                '~' if !self.in_word
+                    && self
+                        .chars
+                        .peek()
+                        .is_none_or(|&c| c == '/' || is_separator(c)) =>
+                {
+                    self.in_word = true;
+                    self.text.push_str(HOME_DIR);
+                }
+                c => {
+                    self.in_word = true;
+                    self.text.push(c);
+                }
+            }
+        }
+        self.end_word();
+
+        Ok(())
+    }
+}
+
+/// Split `line` into tokens.
+/// Quoting errors are reported here (before parsing the command).
+pub fn tokenize(line: &str) -> anyhow::Result<Vec<Token>> {
+    let mut lexer = Lexer::new(line);
+    lexer.run()?;
+
+    Ok(lexer.tokens)
+}
+
+/// `true` if `line` ends in a comment (so a `\` at its end is a part of it).
+pub fn ends_in_comment(line: &str) -> bool {
+    let mut lexer = Lexer::new(line);
+
+    lexer.run().is_ok() && lexer.comment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::cmdline::tests::error;
+
+    /// Words expanded with `$NAME` as `<NAME>`, operators as they are.
+    fn expand(line: &str) -> Vec<String> {
+        tokenize(line)
+            .unwrap()
+            .iter()
+            .flat_map(|token| match token {
+                Token::Word(word) => word.expand(|name| vec![format!("<{name}>")]),
+                token => vec![token.to_string()],
+            })
+            .collect()
+    }
+
+    /// Expansion of each word of `line` with `params` for `$@`
+    /// and empty variables otherwise.
+    fn expand_params(line: &str, params: &[&str]) -> Vec<Vec<String>> {
+        let lookup = |name: &str| match name {
+            "@" => params.iter().map(|param| param.to_string()).collect(),
+            _ => vec![String::new()],
+        };
+        tokenize(line)
+            .unwrap()
+            .iter()
+            .map(|token| match token {
+                Token::Word(word) => word.expand(lookup),
+                _ => panic!("not a word: {token}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(expand(""), [""; 0]);
+        assert_eq!(expand("  ls   -l  "), ["ls", "-l"]);
+        assert_eq!(
+            expand("a&&b||c;d|e<f>g>>h&"),
+            [
+                "a", "&&", "b", "||", "c", ";", "d", "|", "e", "<", "f", ">", "g", ">>", "h", "&"
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        assert_eq!(
+            expand(r#"echo 'a b' "c d" e'f'"g""#),
+            ["echo", "a b", "c d", "efg"]
+        );
+        assert_eq!(expand(r#"'$X' "$X" $X"#), ["$X", "<X>", "<X>"]);
+        assert_eq!(expand(r#""a\"b\\c\$d\x""#), [r#"a"b\c$d\x"#]);
+        assert_eq!(expand(r"a\ b \' \$X \&"), ["a b", "'", "$X", "&"]);
+        assert_eq!(expand("'a|b' \"c;d\""), ["a|b", "c;d"]);
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(expand("${X}y $X_1.z"), ["<X>y", "<X_1>.z"]);
+        assert_eq!(
+            expand("$? $# $@ $* $1 ${10} $12"),
+            ["<?>", "<#>", "<@>", "<*>", "<1>", "<10>", "<1>2"]
+        );
+        assert_eq!(expand("$ a$ \"$\""), ["$", "a$", "$"]);
+    }
+
+    #[test]
+    fn empty_words() {
+        assert_eq!(
+            expand_params("$X \"$X\" ''", &[]),
+            [vec![], vec![""], vec![""]]
+        );
+    }
+
+    #[test]
+    fn params() {
+        assert_eq!(
+            expand_params("$@ \"$@\" x$@ \"x$@y\"", &["a", "b c"]),
+            [
+                vec!["a", "b c"],
+                vec!["a", "b c"],
+                vec!["xa", "b c"],
+                vec!["xa", "b cy"]
+            ]
+        );
+        assert_eq!(
+            expand_params("$@ \"$@\" x$@ \"x$@y\"", &[]),
+            [vec![], vec![], vec!["x"], vec!["xy"]]
+        );
+        assert_eq!(expand_params("\"$@\" $@", &[""]), [vec![""], vec![]]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(expand("echo a#b # c 'd"), ["echo", "a#b"]);
+        assert_eq!(expand("'#a' \\#b"), ["#a", "#b"]);
+        assert_eq!(expand("# only a comment"), [""; 0]);
+        assert!(ends_in_comment("echo a # b \\"));
+        assert!(!ends_in_comment("echo a#b \\"));
+        assert!(!ends_in_comment("echo '# a' \\"));
+        assert!(!ends_in_comment("echo \"# a \\"));
+    }
+
+    #[test]
+    fn tilde() {
+        let home = |rest: &str| format!("{HOME_DIR}{rest}");
+        assert_eq!(
+            expand("~ ~/x ~;"),
+            [home(""), home("/x"), home(""), ";".to_string()]
+        );
+        assert_eq!(expand("a~ ~x '~' \"~\" \\~"), ["a~", "~x", "~", "~", "~"]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |line| error(tokenize(line));
+        assert_eq!(error("echo 'a"), "syntax error: unterminated single quote");
+        assert_eq!(error("echo \"a"), "syntax error: unterminated double quote");
+        assert_eq!(
+            error("echo \"a\\"),
+            "syntax error: unterminated double quote"
+        );
+        assert_eq!(
+            error("echo a\\"),
+            "syntax error: unexpected end of line after '\\'"
+        );
+        assert_eq!(
+            error("${a-b}"),
+            "syntax error: bad substitution '-' in ${...}"
+        );
+        assert_eq!(
+            error("\"${a\""),
+            "syntax error: bad substitution '\"' in ${...}"
+        );
+        assert_eq!(error("${abc"), "syntax error: unterminated ${...}");
+        assert_eq!(error("${}"), "syntax error: bad substitution ${}");
+    }
+}
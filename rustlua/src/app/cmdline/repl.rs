@@ -0,0 +1,101 @@
+//! Interactive Lua mode of the command line.
+//!
+//! Works like the standalone `lua` binary:
+//! * An expression is evaluated and all of its values are printed.
+//!   (`=expr` of Lua 5.3 is also accepted.)
+//! * Input is kept pending until the chunk becomes syntactically complete.
+
+use std::cell::RefCell;
+
+use crate::app::lua;
+
+const CHUNK_NAME: &str = "=stdin";
+
+thread_local! {
+    /// `Some(pending_source)` while in REPL mode.
+    static REPL: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+enum EvalResult {
+    Done,
+    Incomplete,
+}
+
+pub fn is_active() -> bool {
+    REPL.with(|cell| cell.borrow().is_some())
+}
+
+pub fn enter() {
+    REPL.with(|cell| *cell.borrow_mut() = Some(String::new()));
+    println!("Lua REPL (type \"exit\" to return to the shell)");
+}
+
+fn leave() {
+    REPL.with(|cell| *cell.borrow_mut() = None);
+}
+
+/// Process a line in REPL mode.
+pub fn input(line: &str) -> anyhow::Result<()> {
+    let mut pending = REPL.with(|cell| cell.borrow_mut().take().unwrap_or_default());
+
+    let prompt = if pending.is_empty() { ">" } else { ">>" };
+    println!("{prompt} {line}");
+
+    if pending.is_empty() && line.trim() == "exit" {
+        leave();
+        return Ok(());
+    }
+
+    if !pending.is_empty() {
+        pending.push('\n');
+    }
+    pending.push_str(line);
+
+    let result = eval(&pending);
+    let next = match result {
+        Ok(EvalResult::Incomplete) => pending,
+        _ => String::new(),
+    };
+    REPL.with(|cell| *cell.borrow_mut() = Some(next));
+    if let Err(err) = result {
+        lua::report(&err);
+    }
+
+    Ok(())
+}
+
+fn eval(src: &str) -> anyhow::Result<EvalResult> {
+    let lua = lua::lua()?;
+
+    // "=expr" (Lua 5.3 style)
+    let src = match src.strip_prefix('=') {
+        Some(expr) => format!("return {expr}"),
+        None => src.to_string(),
+    };
+
+    // try as an expression first
+    let func = match lua
+        .load(format!("return {src};"))
+        .set_name(CHUNK_NAME)
+        .into_function()
+    {
+        Ok(func) => func,
+        // then as statements
+        Err(_) => match lua.load(src.as_str()).set_name(CHUNK_NAME).into_function() {
+            Ok(func) => func,
+            Err(mlua::Error::SyntaxError {
+                incomplete_input: true,
+                ..
+            }) => return Ok(EvalResult::Incomplete),
+            Err(err) => return Err(err.into()),
+        },
+    };
+
+    let values = lua::call(&func, ())?;
+    if !values.is_empty() {
+        let print: mlua::Function = lua.globals().get("print")?;
+        print.call::<()>(values)?;
+    }
+
+    Ok(EvalResult::Done)
+}
@@ -0,0 +1,200 @@
+//! Shell scripts (`sh` and `source`).
+//!
+//! Each line is run like a command line (without echoing it).
+//! * `#` starts a comment (see [super::lexer])
+//! * a line ending with `\` continues to the next line
+//! * `set -e`: stop the script when a command fails
+//!   (except one followed by `&&` or `||`)
+//! * `$0`: script path, `$1`..`$9` and `${10}`..: arguments,
+//!   `$#`: number of arguments, `$@`: all arguments (one word each),
+//!   `$*`: all arguments as one word
+//!
+//! `source` without arguments keeps those of the caller.
+//! The commands do not read the input of the script.
+//! `sh` restores the working directory at the end and `source` does not.
+
+use std::cell::RefCell;
+use std::io::Write;
+
+use super::{STATUS, Stdio, exit_status, file, lexer};
+
+/// Max depth of scripts running scripts.
+const MAX_DEPTH: usize = 16;
+
+/// A running script.
+struct Frame {
+    path: String,
+    args: Vec<String>,
+    errexit: bool,
+}
+
+thread_local! {
+    static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Arguments of the running script (`$@`).
+pub fn params() -> Vec<String> {
+    FRAMES.with(|cell| {
+        cell.borrow()
+            .last()
+            .map(|frame| frame.args.clone())
+            .unwrap_or_default()
+    })
+}
+
+/// Value of a parameter of the running script (empty outside of scripts).
+/// `None` if `name` is not a positional or special parameter.
+pub fn param(name: &str) -> Option<String> {
+    let is_positional = !name.is_empty() && name.chars().all(|c| c.is_ascii_digit());
+    if !is_positional && !matches!(name, "#" | "*") {
+        return None;
+    }
+
+    FRAMES.with(|cell| {
+        let frames = cell.borrow();
+        let frame = frames.last();
+        let args = frame.map_or(&[][..], |frame| &frame.args[..]);
+        let value = match name {
+            "#" => args.len().to_string(),
+            "*" => args.join(" "),
+            "0" => frame.map(|frame| frame.path.clone()).unwrap_or_default(),
+            n => n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| args.get(n.checked_sub(1)?))
+                .cloned()
+                .unwrap_or_default(),
+        };
+
+        Some(value)
+    })
+}
+
+/// `true` if `set -e` is in effect.
+pub fn errexit() -> bool {
+    FRAMES.with(|cell| cell.borrow().last().is_some_and(|frame| frame.errexit))
+}
+
+/// `set`
+pub fn set(options: &[String], out: &mut dyn Write) -> anyhow::Result<()> {
+    FRAMES.with(|cell| {
+        let mut frames = cell.borrow_mut();
+        let frame = frames
+            .last_mut()
+            .ok_or_else(|| anyhow::anyhow!("set: can only be used in a script"))?;
+
+        if options.is_empty() {
+            let value = if frame.errexit { "on" } else { "off" };
+            writeln!(out, "errexit\t{value}")?;
+        }
+        for option in options {
+            match option.as_str() {
+                "-e" => frame.errexit = true,
+                "+e" => frame.errexit = false,
+                _ => anyhow::bail!("set: {option}: invalid option"),
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Lines joined at a trailing backslash (not in a comment),
+/// with the number of the first line.
+fn join_lines(src: &str) -> Vec<(usize, String)> {
+    let mut res = Vec::new();
+    let mut continued: Option<(usize, String)> = None;
+    for (i, line) in src.lines().enumerate() {
+        let (lineno, mut buf) = continued.take().unwrap_or((i + 1, String::new()));
+        // "\\" at the end is an escaped backslash
+        let backslashes = line.len() - line.trim_end_matches('\\').len();
+        if backslashes % 2 == 1 && !lexer::ends_in_comment(&(buf.clone() + line)) {
+            buf.push_str(&line[..line.len() - 1]);
+            continued = Some((lineno, buf));
+        } else {
+            buf.push_str(line);
+            res.push((lineno, buf));
+        }
+    }
+    res.extend(continued);
+
+    res
+}
+
+/// Run the script at `path`. `subshell`: restore the working directory
+/// at the end (`sh`).
+/// The output of the commands goes to that of `sh` (or `source`).
+pub fn run(path: &str, args: &[String], subshell: bool, stdio: &mut Stdio) -> anyhow::Result<()> {
+    let cmd = if subshell { "sh" } else { "source" };
+    let src = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("{cmd}: {path}: {}", file::io_msg(&e)))?;
+    let depth = FRAMES.with(|cell| cell.borrow().len());
+    anyhow::ensure!(
+        depth < MAX_DEPTH,
+        "{cmd}: {path}: scripts nested too deeply"
+    );
+    let cwd = std::env::current_dir()?;
+
+    FRAMES.with(|cell| {
+        let mut frames = cell.borrow_mut();
+        let args = match frames.last() {
+            Some(frame) if !subshell && args.is_empty() => frame.args.clone(),
+            _ => args.to_vec(),
+        };
+        frames.push(Frame {
+            path: path.to_string(),
+            args,
+            errexit: false,
+        })
+    });
+    STATUS.set(0);
+    for (lineno, line) in join_lines(&src) {
+        match super::run_line(&line, stdio.out, stdio.is_terminal) {
+            Ok(flow) if flow.is_break() => break,
+            Ok(_) => {}
+            Err(err) => {
+                // like a shell, a syntax error stops the script
+                println!("{path}: line {lineno}: {err:#}");
+                STATUS.set(2);
+                break;
+            }
+        }
+    }
+    FRAMES.with(|cell| cell.borrow_mut().pop());
+
+    if subshell {
+        std::env::set_current_dir(&cwd)
+            .map_err(|e| anyhow::anyhow!("{cmd}: {}: {}", cwd.display(), file::io_msg(&e)))?;
+    }
+
+    exit_status(STATUS.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines of `src` as "number: line".
+    fn lines(src: &str) -> Vec<String> {
+        join_lines(src)
+            .iter()
+            .map(|(lineno, line)| format!("{lineno}: {line}"))
+            .collect()
+    }
+
+    #[test]
+    fn continued_lines() {
+        assert_eq!(lines("a \\\nb\nc"), ["1: a b", "3: c"]);
+        assert_eq!(lines("a \\\\\nb"), ["1: a \\\\", "2: b"]);
+        assert_eq!(lines("a \\\n\\\nb"), ["1: a b"]);
+        assert_eq!(lines("a \\"), ["1: a "]);
+    }
+
+    #[test]
+    fn comments_are_not_continued() {
+        assert_eq!(lines("# a \\\nb"), ["1: # a \\", "2: b"]);
+        assert_eq!(lines("a # b \\\nc"), ["1: a # b \\", "2: c"]);
+        assert_eq!(lines("a \\\n# b \\\nc"), ["1: a # b \\", "3: c"]);
+        assert_eq!(lines("a '# b' \\\nc"), ["1: a '# b' c"]);
+    }
+}
@@ -0,0 +1,76 @@
+//! Text processing commands for pipelines (`grep`).
+
+use std::io::Write;
+
+use super::{Stdio, file};
+
+pub struct GrepOptions {
+    pub ignore_case: bool,
+    pub invert: bool,
+    pub line_number: bool,
+    pub count: bool,
+}
+
+/// `pattern` is a fixed string (as `grep -F`).
+/// Return `true` if any line is selected.
+pub fn grep(
+    pattern: &str,
+    paths: &[String],
+    opts: &GrepOptions,
+    stdio: &mut Stdio,
+) -> anyhow::Result<bool> {
+    let stdin = ["-".to_string()];
+    let paths = if paths.is_empty() { &stdin[..] } else { paths };
+    let with_name = paths.len() > 1;
+    let pattern = if opts.ignore_case {
+        pattern.to_lowercase()
+    } else {
+        pattern.to_string()
+    };
+    let write_err = |e: std::io::Error| format!("grep: write error: {}", file::io_msg(&e));
+
+    let mut found = false;
+    file::for_each(paths, |path| {
+        let data = file::read_input("grep", path, stdio)?;
+        let text = String::from_utf8_lossy(&data);
+        let name = if path == "-" {
+            "(standard input)"
+        } else {
+            path
+        };
+        let prefix = if with_name {
+            format!("{name}:")
+        } else {
+            String::new()
+        };
+
+        let mut matched = 0;
+        for (i, line) in text.lines().enumerate() {
+            let found = if opts.ignore_case {
+                line.to_lowercase().contains(&pattern)
+            } else {
+                line.contains(&pattern)
+            };
+            if found == opts.invert {
+                continue;
+            }
+            matched += 1;
+            if opts.count {
+                continue;
+            }
+            if opts.line_number {
+                writeln!(stdio.out, "{prefix}{}:{line}", i + 1).map_err(write_err)?;
+            } else {
+                writeln!(stdio.out, "{prefix}{line}").map_err(write_err)?;
+            }
+        }
+        if opts.count {
+            writeln!(stdio.out, "{prefix}{matched}").map_err(write_err)?;
+        }
+        found |= matched > 0;
+
+        Ok(())
+    })?;
+
+    Ok(found)
+}
@@ -2,7 +2,7 @@
 //! * use only (regular) file or dir
 //! * ignore r/w permissions
 
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use anyhow::bail;
 
@@ -88,6 +88,43 @@ fn ls_rec_body(
     Ok(())
 }
 
+/// Resolve `path` (relative to the current directory) and make sure that
+/// it stays inside `root`.
+///
+/// This is done lexically, without accessing the file system,
+/// so that the result can be a path to be created.
+/// Symbolic links are not considered (we do not create them).
+pub fn jail(root: impl AsRef<Path>, path: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
+    let root = normalize(&std::path::absolute(root)?);
+    let resolved = normalize(&std::path::absolute(path.as_ref())?);
+    if !resolved.starts_with(&root) {
+        bail!(
+            "{}: Permission denied (outside of {})",
+            path.as_ref().display(),
+            root.display()
+        );
+    }
+
+    Ok(resolved)
+}
+
+/// Remove "." and ".." lexically.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                // "/.." is "/"
+                res.pop();
+            }
+            comp => res.push(comp),
+        }
+    }
+
+    res
+}
+
 type FsImage = Vec<FsImageEntry>;
 
 #[derive(serde::Serialize, serde::Deserialize)]
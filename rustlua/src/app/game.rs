@@ -0,0 +1,266 @@
+//! LÖVE-style game loop.
+//!
+//! If a script defines global `update(dt)` and/or `draw()`,
+//! they are called every frame from the main loop after `load()`.
+//! Input callbacks (`keypressed(key)`, `keyreleased(key)`,
+//! `mousepressed(x, y, button)`, `mousereleased(x, y, button)` and
+//! `mousemoved(x, y, dx, dy)`) are called before `update()`.
+//! When a callback raises an error, the loop stops and the error is shown
+//! on the screen until the script is run again or `game stop`.
+
+use std::cell::RefCell;
+
+use crate::app::lua;
+use crate::emapi;
+use crate::emapi::sdl::event::{self, Event};
+use crate::emapi::sdl::{Color, Surface, ttf::Font};
+
+/// Upper limit of `dt` (sec).
+/// Frames are not delivered while the tab is hidden.
+const MAX_DT: f64 = 0.25;
+
+const CALLBACKS: &[&str] = &[
+    "load",
+    "update",
+    "draw",
+    "keypressed",
+    "keyreleased",
+    "mousepressed",
+    "mousereleased",
+    "mousemoved",
+];
+
+enum State {
+    Stopped,
+    Running {
+        last_frame: f64,
+    },
+    Failed {
+        message: String,
+        /// Rendered text lines (created on the first frame).
+        lines: Option<Vec<Surface>>,
+    },
+}
+
+thread_local! {
+    static STATE: RefCell<State> = const { RefCell::new(State::Stopped) };
+}
+
+fn set_state(state: State) {
+    let old = STATE.with(|cell| cell.replace(state));
+    drop(old);
+}
+
+fn fail(err: &anyhow::Error) {
+    let report = lua::report(err);
+    set_state(State::Failed {
+        message: report.message,
+        lines: None,
+    });
+}
+
+/// `true` if the game owns the screen (running or showing an error).
+pub fn is_active() -> bool {
+    STATE.with(|cell| !matches!(*cell.borrow(), State::Stopped))
+}
+
+pub fn status() -> &'static str {
+    STATE.with(|cell| match *cell.borrow() {
+        State::Stopped => "stopped",
+        State::Running { .. } => "running",
+        State::Failed { .. } => "failed",
+    })
+}
+
+/// Start the loop if the script defined the callbacks.
+/// Returns `false` if it did not.
+pub fn start() -> anyhow::Result<bool> {
+    set_state(State::Stopped);
+
+    if lua::user_function("update")?.is_none() && lua::user_function("draw")?.is_none() {
+        return Ok(false);
+    }
+
+    if let Some(load) = lua::user_function("load")?
+        && let Err(err) = lua::guarded(|| load.call::<()>(()))
+    {
+        fail(&err);
+        return Ok(true);
+    }
+
+    set_state(State::Running {
+        last_frame: emapi::emscripten::performance_now(),
+    });
+    log::info!("game loop started");
+
+    Ok(true)
+}
+
+pub fn stop() {
+    set_state(State::Stopped);
+}
+
+/// Forget the callbacks so that the next script does not inherit them.
+/// Builtins with the same name (`load`) are restored.
+pub fn clear_callbacks() -> anyhow::Result<()> {
+    let lua = lua::lua()?;
+    for name in CALLBACKS {
+        if lua::user_function(name)?.is_some() {
+            lua.globals().set(*name, lua::builtin_global(name)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Call the input callback for `event` if defined.
+fn dispatch(event: &Event) -> anyhow::Result<()> {
+    fn call(name: &str, args: impl mlua::IntoLuaMulti) -> anyhow::Result<()> {
+        if let Some(func) = lua::user_function(name)? {
+            lua::guarded(|| func.call::<()>(args))?;
+        }
+        Ok(())
+    }
+
+    match *event {
+        Event::KeyDown { sym, .. } if lua::input::take_press(sym) => {
+            if let Some(key) = event::key_name(sym) {
+                call("keypressed", key)?;
+            }
+        }
+        Event::KeyUp { sym, .. } => {
+            if let Some(key) = event::key_name(sym) {
+                call("keyreleased", key)?;
+            }
+        }
+        Event::MouseButtonDown { button, x, y } => call("mousepressed", (x, y, button))?,
+        Event::MouseButtonUp { button, x, y } => call("mousereleased", (x, y, button))?,
+        Event::MouseMotion {
+            x, y, xrel, yrel, ..
+        } => call("mousemoved", (x, y, xrel, yrel))?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Returns `false` if the callbacks are gone (e.g. `session reset`).
+fn step(dt: f64, events: &[Event]) -> anyhow::Result<bool> {
+    if lua::user_function("update")?.is_none() && lua::user_function("draw")?.is_none() {
+        return Ok(false);
+    }
+
+    for event in events {
+        dispatch(event)?;
+    }
+
+    // may be redefined by the input callbacks
+    let update = lua::user_function("update")?;
+    let draw = lua::user_function("draw")?;
+    if let Some(update) = update {
+        lua::guarded(|| update.call::<()>(dt))?;
+    }
+    if let Some(draw) = draw {
+        lua::guarded(|| draw.call::<()>(()))?;
+    }
+
+    Ok(true)
+}
+
+/// Lines of the error screen, wrapped at `MAX_COLUMNS`.
+/// NUL (which cannot be rendered) is shown as `\0`.
+fn error_lines(message: &str) -> Vec<String> {
+    const MAX_COLUMNS: usize = 70;
+
+    let mut lines = vec!["Lua error (run the script again to restart)".to_string()];
+    for line in message.lines() {
+        let line = line.replace('\t', "    ").replace('\0', "\\0");
+        let chars: Vec<char> = line.chars().collect();
+        for chunk in chars.chunks(MAX_COLUMNS) {
+            lines.push(chunk.iter().collect());
+        }
+    }
+
+    lines
+}
+
+fn render_error(surface: &Surface, font: &Font, message: &str) -> anyhow::Result<Vec<Surface>> {
+    let mut res = Vec::new();
+    for line in error_lines(message).iter().filter(|line| !line.is_empty()) {
+        res.push(font.render(line, Color::RED)?);
+    }
+    surface.fill(Color::BLACK)?;
+
+    Ok(res)
+}
+
+/// Called every frame from the main loop while [is_active()].
+pub fn frame(surface: &Surface, font: &Font, events: &[Event]) {
+    const LINE_HEIGHT: i32 = 20;
+
+    let now = emapi::emscripten::performance_now();
+    let last_frame = STATE.with(|cell| match *cell.borrow() {
+        State::Running { last_frame } => Some(last_frame),
+        _ => None,
+    });
+
+    if let Some(last_frame) = last_frame {
+        let dt = ((now - last_frame) / 1000.0).clamp(0.0, MAX_DT);
+        match step(dt, events) {
+            Ok(true) => STATE.with(|cell| {
+                // may be stopped or restarted by the callbacks
+                if let State::Running { last_frame } = &mut *cell.borrow_mut() {
+                    *last_frame = now;
+                }
+            }),
+            Ok(false) => stop(),
+            Err(err) => fail(&err),
+        }
+        lua::gfx::finish();
+    }
+
+    STATE.with(|cell| {
+        if let State::Failed { message, lines } = &mut *cell.borrow_mut() {
+            if lines.is_none() {
+                match render_error(surface, font, message) {
+                    Ok(rendered) => *lines = Some(rendered),
+                    Err(err) => {
+                        log::error!("{err:#}");
+                        *lines = Some(Vec::new());
+                    }
+                }
+            }
+            for (i, line) in lines.iter().flatten().enumerate() {
+                if let Err(err) = line.blit_to(surface, 8, 8 + i as i32 * LINE_HEIGHT) {
+                    log::error!("{err:#}");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_lines_are_wrapped() {
+        let long = "x".repeat(150);
+        let lines = error_lines(&format!("a\tb\n\n{long}\nc\0d"));
+        assert_eq!(
+            lines[1..],
+            [
+                "a    b".to_string(),
+                "x".repeat(70),
+                "x".repeat(70),
+                "x".repeat(10),
+                "c\\0d".to_string(),
+            ]
+        );
+        // multi-byte characters are not split
+        assert_eq!(
+            error_lines(&"é".repeat(71))[1..],
+            ["é".repeat(70), "é".to_string()]
+        );
+    }
+}
@@ -0,0 +1,618 @@
+//! Long-lived Lua session.
+//!
+//! One [mlua::Lua] state is kept alive across runs so that globals,
+//! loaded modules and functions survive between executions.
+//! JS/WASM is single threaded, so the session is a thread local.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::Context;
+
+use crate::app::fs::HOME_DIR;
+use crate::emapi;
+
+mod audio;
+mod font;
+mod fs;
+pub mod gfx;
+mod image;
+pub mod input;
+mod json;
+mod limit;
+mod report;
+mod sandbox;
+pub mod sched;
+pub mod stdin;
+
+pub use limit::Limits;
+pub use report::report;
+
+thread_local! {
+    static SESSION: RefCell<Option<Session>> = const { RefCell::new(None) };
+    /// Output of `print()` goes here instead of stdout if set.
+    static PRINT_CAPTURE: RefCell<Option<Rc<RefCell<String>>>> = const { RefCell::new(None) };
+    // kept across resets
+    static CONFIG: RefCell<Config> = RefCell::new(Config::default());
+}
+
+/// Standard libraries which can be selected (the base library is always loaded).
+/// `debug` is unsafe and not available.
+pub const STD_LIBS: &[(&str, mlua::StdLib)] = &[
+    ("coroutine", mlua::StdLib::COROUTINE),
+    ("table", mlua::StdLib::TABLE),
+    ("io", mlua::StdLib::IO),
+    ("os", mlua::StdLib::OS),
+    ("string", mlua::StdLib::STRING),
+    ("utf8", mlua::StdLib::UTF8),
+    ("math", mlua::StdLib::MATH),
+    ("package", mlua::StdLib::PACKAGE),
+];
+
+/// Parse comma separated library names (or "all").
+pub fn parse_libs(list: &str) -> anyhow::Result<mlua::StdLib> {
+    let mut libs = mlua::StdLib::NONE;
+    for name in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if name == "all" {
+            libs |= mlua::StdLib::ALL_SAFE;
+        } else if let Some((_, lib)) = STD_LIBS.iter().find(|(n, _)| *n == name) {
+            libs |= *lib;
+        } else {
+            anyhow::bail!("Unknown library: {name}");
+        }
+    }
+
+    Ok(libs)
+}
+
+pub fn lib_names(libs: mlua::StdLib) -> Vec<&'static str> {
+    STD_LIBS
+        .iter()
+        .filter(|(_, lib)| libs.contains(*lib))
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Session settings which survive [reset()].
+#[derive(Clone)]
+pub struct Config {
+    /// Directories searched by `require()` in addition to [HOME_DIR].
+    pub search_roots: Vec<PathBuf>,
+    /// Standard libraries loaded by the next [reset()].
+    pub libs: mlua::StdLib,
+    /// File access of `io`/`os` is confined to this directory.
+    /// `None` means no restriction.
+    pub sandbox_root: Option<PathBuf>,
+    /// Execution limits of each run.
+    pub limits: Limits,
+    /// Maximum size of the Lua heap (bytes).
+    pub memory_limit: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            search_roots: Vec::new(),
+            libs: mlua::StdLib::ALL_SAFE,
+            sandbox_root: Some(PathBuf::from(HOME_DIR)),
+            limits: Limits::default(),
+            memory_limit: Some(64 * 1024 * 1024),
+        }
+    }
+}
+
+impl Config {
+    /// All directories searched by `require()` in order.
+    pub fn module_roots(&self) -> Vec<PathBuf> {
+        let mut roots = vec![PathBuf::from(HOME_DIR)];
+        roots.extend(self.search_roots.iter().cloned());
+
+        roots
+    }
+}
+
+pub struct Session {
+    lua: mlua::Lua,
+    libs: mlua::StdLib,
+    sandbox_root: Option<PathBuf>,
+    monitor: Rc<limit::Monitor>,
+    /// Globals which existed just after the state was created
+    /// (kept so that overwritten builtins can be restored).
+    builtin_globals: HashMap<String, mlua::Value>,
+    /// Entries of `package.loaded` just after the state was created.
+    builtin_modules: HashSet<String>,
+    created_at: f64,
+    exec_count: u64,
+}
+
+pub struct Status {
+    pub uptime_ms: f64,
+    pub exec_count: u64,
+    pub used_memory: usize,
+    pub peak_memory: usize,
+    pub libs: mlua::StdLib,
+    pub sandbox_root: Option<PathBuf>,
+    /// Global names defined by user code (sorted).
+    pub user_globals: Vec<String>,
+    /// Modules loaded by `require()` (sorted).
+    pub user_modules: Vec<String>,
+    /// Pending tasks of `spawn()`.
+    pub tasks: usize,
+}
+
+impl Session {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let libs = config.libs;
+        let options = mlua::LuaOptions::new().catch_rust_panics(true);
+        let lua = mlua::Lua::new_with(libs, options)?;
+        setup_os(&lua)?;
+        setup_print(&lua)?;
+        setup_package(&lua, config)?;
+        if let Some(root) = &config.sandbox_root {
+            sandbox::apply(&lua, root)?;
+        }
+        fs::open(&lua)?;
+        gfx::open(&lua)?;
+        image::open(&lua)?;
+        font::open(&lua)?;
+        audio::open(&lua)?;
+        json::open(&lua)?;
+        input::open(&lua)?;
+        sched::open(&lua)?;
+        stdin::open(&lua)?;
+        let monitor = limit::install(&lua)?;
+        lua.set_memory_limit(config.memory_limit.unwrap_or(0))?;
+
+        let builtin_globals = global_values(&lua)?;
+        let builtin_modules = loaded_modules(&lua)?.into_iter().collect();
+
+        Ok(Self {
+            lua,
+            libs,
+            sandbox_root: config.sandbox_root.clone(),
+            monitor,
+            builtin_globals,
+            builtin_modules,
+            created_at: emapi::emscripten::performance_now(),
+            exec_count: 0,
+        })
+    }
+
+    fn builtin_pointer(&self, name: &str) -> Option<usize> {
+        let value = self.builtin_globals.get(name)?;
+        Some(value.to_pointer() as usize)
+    }
+
+    pub fn status(&self) -> anyhow::Result<Status> {
+        let mut user_globals: Vec<_> = global_pointers(&self.lua)?
+            .into_iter()
+            .filter(|(name, ptr)| self.builtin_pointer(name) != Some(*ptr))
+            .map(|(name, _)| name)
+            .collect();
+        user_globals.sort();
+        let mut user_modules: Vec<_> = loaded_modules(&self.lua)?
+            .into_iter()
+            .filter(|name| !self.builtin_modules.contains(name))
+            .collect();
+        user_modules.sort();
+
+        Ok(Status {
+            uptime_ms: emapi::emscripten::performance_now() - self.created_at,
+            exec_count: self.exec_count,
+            used_memory: self.lua.used_memory(),
+            peak_memory: self.monitor.sample_memory(&self.lua),
+            libs: self.libs,
+            sandbox_root: self.sandbox_root.clone(),
+            user_globals,
+            user_modules,
+            tasks: sched::count(&self.lua),
+        })
+    }
+}
+
+/// Make `require("foo.bar")` search `<root>/foo/bar.lua` and
+/// `<root>/foo/bar/init.lua` for each root.
+///
+/// Lua's own searcher is used as is (replaced by a confined one in
+/// [sandbox]), so `package.loaded` works as the module cache and
+/// "module not found" errors list all the tried paths.
+fn setup_package(lua: &mlua::Lua, config: &Config) -> anyhow::Result<()> {
+    let package = if let Ok(package) = lua.globals().get::<mlua::Table>("package") {
+        package
+    } else {
+        return Ok(());
+    };
+
+    let path = config
+        .module_roots()
+        .iter()
+        .map(|root| {
+            let root = root.to_string_lossy();
+            format!("{root}/?.lua;{root}/?/init.lua")
+        })
+        .collect::<Vec<_>>()
+        .join(";");
+    package.set("path", path)?;
+    // no dynamic library on wasm
+    package.set("cpath", "")?;
+
+    Ok(())
+}
+
+/// The current state (without creating the session).
+fn current() -> Option<mlua::Lua> {
+    SESSION.with(|cell| cell.borrow().as_ref().map(|s| s.lua.clone()))
+}
+
+/// Set a native module as a global and to `package.loaded`
+/// (so that `require(name)` also works).
+fn register_module(lua: &mlua::Lua, name: &str, module: mlua::Table) -> anyhow::Result<()> {
+    if let Ok(package) = lua.globals().get::<mlua::Table>("package") {
+        package
+            .get::<mlua::Table>("loaded")?
+            .set(name, module.clone())?;
+    }
+    lua.globals().set(name, module)?;
+
+    Ok(())
+}
+
+/// Create a Lua function which returns `nil, err` on [Err]
+/// instead of raising an error (like `io.open()`).
+/// Invalid arguments still raise errors.
+fn create_fallible_function<A, R, F>(lua: &mlua::Lua, func: F) -> mlua::Result<mlua::Function>
+where
+    A: mlua::FromLuaMulti,
+    R: mlua::IntoLuaMulti,
+    F: Fn(&mlua::Lua, A) -> anyhow::Result<R> + 'static,
+{
+    lua.create_function(move |lua, args: A| match func(lua, args) {
+        Ok(ret) => mlua::IntoLuaMulti::into_lua_multi(ret, lua),
+        Err(err) => mlua::IntoLuaMulti::into_lua_multi((mlua::Value::Nil, format!("{err:#}")), lua),
+    })
+}
+
+fn loaded_modules(lua: &mlua::Lua) -> anyhow::Result<Vec<String>> {
+    let loaded = lua
+        .globals()
+        .get::<mlua::Table>("package")
+        .and_then(|package| package.get::<mlua::Table>("loaded"));
+    match loaded {
+        Ok(loaded) => table_keys(&loaded),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Replace `print()` so that its output can be captured
+/// (see [capture_print()]).
+fn setup_print(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let print = lua.create_function(|_, args: mlua::MultiValue| {
+        let mut line = String::new();
+        for (i, value) in args.iter().enumerate() {
+            if i > 0 {
+                line.push('\t');
+            }
+            line.push_str(&value.to_string()?);
+        }
+
+        let captured = PRINT_CAPTURE.with(|cell| cell.borrow().clone());
+        match captured {
+            Some(buf) => {
+                let mut buf = buf.borrow_mut();
+                buf.push_str(&line);
+                buf.push('\n');
+            }
+            None => println!("{line}"),
+        }
+
+        Ok(())
+    })?;
+    lua.globals().set("print", print)?;
+
+    Ok(())
+}
+
+/// Run `f` with the output of `print()` appended to `buf`.
+pub fn capture_print<R>(buf: &Rc<RefCell<String>>, f: impl FnOnce() -> R) -> R {
+    let prev = PRINT_CAPTURE.with(|cell| cell.replace(Some(buf.clone())));
+    let res = f();
+    PRINT_CAPTURE.with(|cell| cell.replace(prev));
+
+    res
+}
+
+/// Replace functions which would break the page.
+fn setup_os(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let os = if let Ok(os) = lua.globals().get::<mlua::Table>("os") {
+        os
+    } else {
+        return Ok(());
+    };
+
+    // exit() would kill the whole wasm runtime
+    // raise an error which can be detected by exit_code() instead
+    let exit = lua.create_function(
+        |_, (code, _close): (Option<mlua::Value>, Option<bool>)| -> mlua::Result<()> {
+            let code = match code {
+                None | Some(mlua::Value::Nil) | Some(mlua::Value::Boolean(true)) => 0,
+                Some(mlua::Value::Boolean(false)) => 1,
+                Some(mlua::Value::Integer(n)) => n as i32,
+                Some(mlua::Value::Number(n)) => n as i32,
+                Some(v) => {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "bad argument #1 to 'exit' (number expected, got {})",
+                        v.type_name()
+                    )));
+                }
+            };
+            Err(mlua::Error::external(ExitRequest(code)))
+        },
+    )?;
+    os.set("exit", exit)?;
+
+    Ok(())
+}
+
+/// Error object raised by `os.exit()`.
+#[derive(Debug)]
+struct ExitRequest(i32);
+
+impl std::fmt::Display for ExitRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "os.exit({})", self.0)
+    }
+}
+
+impl std::error::Error for ExitRequest {}
+
+/// If `err` was caused by `os.exit()`, returns its exit code.
+pub fn exit_code(err: &anyhow::Error) -> Option<i32> {
+    find_external::<ExitRequest>(err).map(|e| e.0)
+}
+
+/// Find an error object of type `T` raised from Rust code.
+fn find_external<T: std::error::Error + 'static>(err: &anyhow::Error) -> Option<&T> {
+    fn find<T: std::error::Error + 'static>(err: &mlua::Error) -> Option<&T> {
+        match err {
+            mlua::Error::CallbackError { cause, .. } => find(cause),
+            mlua::Error::WithContext { cause, .. } => find(cause),
+            mlua::Error::ExternalError(err) => err.downcast_ref::<T>(),
+            _ => None,
+        }
+    }
+
+    err.downcast_ref::<mlua::Error>().and_then(find)
+}
+
+fn global_values(lua: &mlua::Lua) -> anyhow::Result<HashMap<String, mlua::Value>> {
+    let mut res = HashMap::new();
+    for pair in lua.globals().pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = pair?;
+        if let mlua::Value::String(s) = key {
+            res.insert(s.to_string_lossy(), value);
+        }
+    }
+
+    Ok(res)
+}
+
+/// Identify global values by their pointers
+/// to detect builtins overwritten by user code.
+fn global_pointers(lua: &mlua::Lua) -> anyhow::Result<HashMap<String, usize>> {
+    Ok(global_values(lua)?
+        .into_iter()
+        .map(|(name, value)| (name, value.to_pointer() as usize))
+        .collect())
+}
+
+fn table_keys(table: &mlua::Table) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for pair in table.pairs::<mlua::Value, mlua::Value>() {
+        let (key, _) = pair?;
+        if let mlua::Value::String(s) = key {
+            names.push(s.to_string_lossy());
+        }
+    }
+
+    Ok(names)
+}
+
+/// Get a handle of the current Lua state.
+/// The session is created on the first call.
+///
+/// [mlua::Lua] is a reference counted handle, so the session is not
+/// borrowed while Lua code is running and the code can call back into
+/// this module.
+pub fn lua() -> anyhow::Result<mlua::Lua> {
+    SESSION.with(|cell| {
+        let mut session = cell.borrow_mut();
+        if session.is_none() {
+            *session = Some(Session::new(&config())?);
+            log::info!("Lua session created");
+        }
+        Ok(session.as_ref().unwrap().lua.clone())
+    })
+}
+
+/// Compile and execute `src` in the session.
+///
+/// * `name`: chunk name (see `lua_exec()`)
+pub fn exec(src: &str, name: &str) -> anyhow::Result<()> {
+    let lua = lua()?;
+    let func = lua.load(src).set_name(name).into_function()?;
+    call(&func, ())?;
+
+    Ok(())
+}
+
+/// Compile a Lua source file.
+///
+/// The chunk name is `@path`, so that error messages refer to the file.
+pub fn load_file(path: &str) -> anyhow::Result<mlua::Function> {
+    let mut src = std::fs::read(path).with_context(|| format!("cannot open {path}"))?;
+    // skip "#!" line (keep the newline for line numbers)
+    if src.first() == Some(&b'#') {
+        let end = src.iter().position(|&c| c == b'\n').unwrap_or(src.len());
+        src.drain(..end);
+    }
+
+    let lua = lua()?;
+    Ok(lua.load(src).set_name(format!("@{path}")).into_function()?)
+}
+
+/// Load a Lua source file and call it with `args` as `...`.
+pub fn exec_file(path: &str, args: &[String]) -> anyhow::Result<()> {
+    let func = load_file(path)?;
+    let args: mlua::Variadic<String> = args.iter().cloned().collect();
+    call(&func, args)?;
+
+    Ok(())
+}
+
+/// Set the global `arg` table like the standalone `lua` binary.
+///
+/// `arg[0]` is the script name, `arg[1..]` are `args`
+/// and `head` (interpreter name and options) gets negative indices.
+pub fn set_arg(head: &[String], script: &str, args: &[String]) -> anyhow::Result<()> {
+    let lua = lua()?;
+    let arg = lua.create_table()?;
+    for (i, s) in head.iter().rev().enumerate() {
+        arg.raw_set(-(i as i64) - 1, s.as_str())?;
+    }
+    arg.raw_set(0, script)?;
+    for (i, s) in args.iter().enumerate() {
+        arg.raw_set(i as i64 + 1, s.as_str())?;
+    }
+    lua.globals().set("arg", arg)?;
+
+    Ok(())
+}
+
+/// Call a top-level function (e.g. a compiled chunk) in the session.
+pub fn call(
+    func: &mlua::Function,
+    args: impl mlua::IntoLuaMulti,
+) -> anyhow::Result<mlua::MultiValue> {
+    SESSION.with(|cell| {
+        if let Some(session) = cell.borrow_mut().as_mut() {
+            session.exec_count += 1;
+        }
+    });
+
+    guarded(|| func.call::<mlua::MultiValue>(args))
+}
+
+/// Resume `thread` (e.g. a background job) for up to `slice_ms`.
+///
+/// The thread is made to yield when the time slice is used up,
+/// so that a busy loop does not block the main loop.
+pub fn resume_slice(
+    thread: &mlua::Thread,
+    args: impl mlua::IntoLuaMulti,
+    slice_ms: f64,
+) -> anyhow::Result<mlua::MultiValue> {
+    let monitor = SESSION.with(|cell| cell.borrow().as_ref().map(|s| s.monitor.clone()));
+    let _slice = monitor
+        .as_ref()
+        .map(|monitor| limit::time_slice(monitor, thread, slice_ms));
+
+    guarded(|| thread.resume::<mlua::MultiValue>(args))
+}
+
+/// Run `f` with the execution limits (see [Config::limits]) armed.
+pub fn guarded<R>(f: impl FnOnce() -> mlua::Result<R>) -> anyhow::Result<R> {
+    let session = SESSION.with(|cell| {
+        cell.borrow()
+            .as_ref()
+            .map(|s| (s.lua.clone(), s.monitor.clone()))
+    });
+    let config = config();
+    let _guard = session
+        .as_ref()
+        .map(|(_, monitor)| limit::arm(monitor, config.limits));
+
+    let res = f();
+    if let Some((lua, monitor)) = &session {
+        monitor.sample_memory(lua);
+    }
+
+    match res {
+        Ok(ret) => Ok(ret),
+        Err(err @ mlua::Error::MemoryError(_)) => {
+            let limit = config.memory_limit.unwrap_or(0);
+            Err(anyhow::Error::new(err)
+                .context(format!("out of memory (Lua heap limit: {limit} B)")))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Discard the current Lua state and start a new one.
+pub fn reset() -> anyhow::Result<()> {
+    let session = Session::new(&config())?;
+    let old = SESSION.with(|cell| cell.replace(Some(session)));
+    // drop the old state outside of the borrow (__gc may run)
+    drop(old);
+    // nobody is waiting for the queued input any more
+    stdin::clear();
+    log::info!("Lua session reset");
+
+    Ok(())
+}
+
+/// Get a global function defined by user code.
+///
+/// Returns `None` if it is not defined, not a function or still a builtin
+/// (e.g. `load` is a callback name of the game loop and also a builtin).
+pub fn user_function(name: &str) -> anyhow::Result<Option<mlua::Function>> {
+    let lua = lua()?;
+    let func = match lua.globals().get::<mlua::Value>(name)? {
+        mlua::Value::Function(func) => func,
+        _ => return Ok(None),
+    };
+    let ptr = func.to_pointer() as usize;
+    let builtin = SESSION.with(|cell| cell.borrow().as_ref()?.builtin_pointer(name));
+
+    Ok((builtin != Some(ptr)).then_some(func))
+}
+
+/// The value of global `name` when the session was created
+/// (`nil` if it was not defined).
+pub fn builtin_global(name: &str) -> anyhow::Result<mlua::Value> {
+    // create the session if not yet
+    lua()?;
+    SESSION.with(|cell| {
+        let session = cell.borrow();
+        let value = session.as_ref().unwrap().builtin_globals.get(name).cloned();
+        Ok(value.unwrap_or(mlua::Value::Nil))
+    })
+}
+
+pub fn status() -> anyhow::Result<Status> {
+    // create the session if not yet
+    lua()?;
+    SESSION.with(|cell| cell.borrow().as_ref().unwrap().status())
+}
+
+pub fn config() -> Config {
+    CONFIG.with(|cell| cell.borrow().clone())
+}
+
+/// Modify the settings and apply them to the current state.
+/// (`libs` and `sandbox_root` take effect on the next [reset()].)
+pub fn update_config(f: impl FnOnce(&mut Config)) -> anyhow::Result<()> {
+    let config = CONFIG.with(|cell| {
+        let mut config = cell.borrow_mut();
+        f(&mut config);
+        config.clone()
+    });
+
+    if let Some(lua) = current() {
+        setup_package(&lua, &config)?;
+        lua.set_memory_limit(config.memory_limit.unwrap_or(0))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,201 @@
+//! `audio` module for Lua.
+//!
+//! * `audio.load(path)` loads a sound file (WAV/OGG/MP3).
+//! * `snd:play([{loop = bool, volume = 0..1}])` plays it on a free channel
+//!   and returns a channel handle.
+//! * `ch:stop()`, `ch:pause()`, `ch:resume()`, `ch:volume([v])`,
+//!   `ch:playing()`
+//! * `audio.setVolume(v)`, `audio.getVolume()`: master volume (0..1)
+//! * `audio.stopAll()`
+//!
+//! Mixer channels are reused after a sound finishes, so each play gets
+//! an id and a handle only controls its channel while the id matches.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::app::fs::{self as appfs, HOME_DIR};
+use crate::emapi::sdl::mixer;
+
+struct Playing {
+    id: u64,
+    volume: f64,
+}
+
+struct Mixer {
+    master: f64,
+    next_id: u64,
+    channels: HashMap<i32, Playing>,
+}
+
+thread_local! {
+    static MIXER: RefCell<Mixer> = RefCell::new(Mixer {
+        master: 1.0,
+        next_id: 1,
+        channels: HashMap::new(),
+    });
+}
+
+fn apply_volume(channel: i32, volume: f64, master: f64) {
+    let volume = (volume * master * mixer::MAX_VOLUME as f64).round() as i32;
+    mixer::set_volume(channel, volume);
+}
+
+struct Sound(Rc<mixer::Chunk>);
+
+struct Channel {
+    channel: i32,
+    id: u64,
+    /// Keep the chunk while playing.
+    _chunk: Rc<mixer::Chunk>,
+}
+
+impl Channel {
+    /// `true` if the channel still plays this sound.
+    fn is_current(&self) -> bool {
+        let owned = MIXER.with(|cell| {
+            cell.borrow()
+                .channels
+                .get(&self.channel)
+                .is_some_and(|p| p.id == self.id)
+        });
+
+        owned && (mixer::is_playing(self.channel) || mixer::is_paused(self.channel))
+    }
+}
+
+fn volume_arg(volume: f64) -> mlua::Result<f64> {
+    if !(0.0..=1.0).contains(&volume) {
+        return Err(mlua::Error::RuntimeError(format!(
+            "volume must be in 0..1 (got {volume})"
+        )));
+    }
+
+    Ok(volume)
+}
+
+impl mlua::UserData for Sound {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("play", |_, this, opts: Option<mlua::Table>| {
+            let (looping, volume) = match opts {
+                Some(opts) => (
+                    opts.get::<Option<bool>>("loop")?.unwrap_or(false),
+                    volume_arg(opts.get::<Option<f64>>("volume")?.unwrap_or(1.0))?,
+                ),
+                None => (false, 1.0),
+            };
+
+            let channel = this
+                .0
+                .play_channel(if looping { -1 } else { 0 })
+                .map_err(mlua::Error::external)?;
+            let id = MIXER.with(|cell| {
+                let mut mixer = cell.borrow_mut();
+                // forget finished sounds
+                mixer
+                    .channels
+                    .retain(|&channel, _| mixer::is_playing(channel) || mixer::is_paused(channel));
+                let id = mixer.next_id;
+                mixer.next_id += 1;
+                mixer.channels.insert(channel, Playing { id, volume });
+                apply_volume(channel, volume, mixer.master);
+
+                id
+            });
+
+            Ok(Channel {
+                channel,
+                id,
+                _chunk: this.0.clone(),
+            })
+        });
+    }
+}
+
+impl mlua::UserData for Channel {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("stop", |_, this, ()| {
+            if this.is_current() {
+                mixer::halt(this.channel);
+            }
+            Ok(())
+        });
+        methods.add_method("pause", |_, this, ()| {
+            if this.is_current() {
+                mixer::pause(this.channel);
+            }
+            Ok(())
+        });
+        methods.add_method("resume", |_, this, ()| {
+            if this.is_current() {
+                mixer::resume(this.channel);
+            }
+            Ok(())
+        });
+        methods.add_method("playing", |_, this, ()| {
+            Ok(this.is_current() && !mixer::is_paused(this.channel))
+        });
+        methods.add_method("volume", |_, this, volume: Option<f64>| {
+            let current = this.is_current();
+            MIXER.with(|cell| {
+                let mut mixer = cell.borrow_mut();
+                let master = mixer.master;
+                let playing = mixer.channels.get_mut(&this.channel);
+                match (volume, playing) {
+                    (Some(volume), Some(playing)) if current => {
+                        playing.volume = volume_arg(volume)?;
+                        apply_volume(this.channel, playing.volume, master);
+                        Ok(Some(playing.volume))
+                    }
+                    (None, Some(playing)) if current => Ok(Some(playing.volume)),
+                    _ => Ok(None),
+                }
+            })
+        });
+    }
+}
+
+fn load(_: &mlua::Lua, path: String) -> anyhow::Result<Sound> {
+    anyhow::ensure!(!path.contains('\0'), "{path:?}: invalid path");
+    let resolved = appfs::jail(HOME_DIR, &path)?;
+    anyhow::ensure!(resolved.is_file(), "{path}: No such file");
+    let chunk =
+        mixer::load(&resolved.to_string_lossy()).map_err(|e| anyhow::anyhow!("{path}: {e:#}"))?;
+
+    Ok(Sound(Rc::new(chunk)))
+}
+
+pub fn open(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let audio = lua.create_table()?;
+
+    audio.set("load", super::create_fallible_function(lua, load)?)?;
+    audio.set(
+        "setVolume",
+        lua.create_function(|_, volume: f64| {
+            let volume = volume_arg(volume)?;
+            MIXER.with(|cell| {
+                let mut mixer = cell.borrow_mut();
+                mixer.master = volume;
+                for (&channel, playing) in &mixer.channels {
+                    apply_volume(channel, playing.volume, volume);
+                }
+            });
+            Ok(())
+        })?,
+    )?;
+    audio.set(
+        "getVolume",
+        lua.create_function(|_, ()| Ok(MIXER.with(|cell| cell.borrow().master)))?,
+    )?;
+    audio.set(
+        "stopAll",
+        lua.create_function(|_, ()| {
+            mixer::halt(-1);
+            MIXER.with(|cell| cell.borrow_mut().channels.clear());
+            Ok(())
+        })?,
+    )?;
+
+    super::register_module(lua, "audio", audio)
+}
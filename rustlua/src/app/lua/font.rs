@@ -0,0 +1,232 @@
+//! `font` module for Lua.
+//!
+//! * `font.open(name_or_path, size)` opens a font file (TTF/OTF) or,
+//!   if no such file exists, a font family known to the browser
+//!   (e.g. "monospace").
+//! * `f:draw(text, x, y[, color])` draws text to the video surface.
+//!   `color` is `{r, g, b}` (default: the current `gfx` color).
+//! * `f:measure(text)` returns width and height.
+//!
+//! Emscripten SDL_ttf renders with the canvas API and takes a CSS font
+//! family instead of a file, so font files are registered as `FontFace`.
+//! They are loaded asynchronously and a fallback font is used until then.
+//!
+//! Rendered text is cached per font so that drawing the same string
+//! every frame does not create a new surface each time.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::app::fs::{self as appfs, HOME_DIR};
+use crate::emapi;
+use crate::emapi::sdl::{Color, Surface, ttf};
+
+use super::gfx;
+
+/// Max number of cached text surfaces per font.
+const CACHE_SIZE: usize = 128;
+
+thread_local! {
+    /// Font file => registered CSS font family.
+    static FAMILIES: RefCell<HashMap<PathBuf, String>> = RefCell::new(HashMap::new());
+}
+
+/// Register a font file to the document and return its family name.
+fn register_font_file(path: &Path) -> anyhow::Result<String> {
+    if let Some(family) = FAMILIES.with(|cell| cell.borrow().get(path).cloned()) {
+        return Ok(family);
+    }
+
+    let data = std::fs::read(path)?;
+    let base64 = {
+        use base64::Engine;
+        use base64::prelude::*;
+        BASE64_STANDARD.encode(data)
+    };
+    let family = FAMILIES.with(|cell| format!("luafont{}", cell.borrow().len() + 1));
+    emapi::emscripten::eval_js(&format!(
+        r"
+(() => {{
+    try {{
+        const face = new FontFace('{family}', 'url(data:application/octet-stream;base64,{base64})');
+        document.fonts.add(face);
+        face.load().catch((e) => console.error(e));
+    }}
+    catch (e) {{ console.error(e); }}
+}})()"
+    ));
+    FAMILIES.with(|cell| cell.borrow_mut().insert(path.to_path_buf(), family.clone()));
+
+    Ok(family)
+}
+
+/// Cache of rendered text with least-recently-used eviction.
+#[derive(Default)]
+struct TextCache {
+    entries: HashMap<(String, Color), (Surface, u64)>,
+    clock: u64,
+}
+
+impl TextCache {
+    fn get_or_render(
+        &mut self,
+        font: &ttf::Font,
+        text: &str,
+        color: Color,
+    ) -> anyhow::Result<&Surface> {
+        self.clock += 1;
+        let key = (text.to_string(), color);
+        if !self.entries.contains_key(&key) {
+            if self.entries.len() >= CACHE_SIZE
+                && let Some(oldest) = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, (_, used))| *used)
+                    .map(|(key, _)| key.clone())
+            {
+                self.entries.remove(&oldest);
+            }
+            let surface = font.render(text, color)?;
+            self.entries.insert(key.clone(), (surface, 0));
+        }
+
+        let (surface, used) = self.entries.get_mut(&key).unwrap();
+        *used = self.clock;
+
+        Ok(surface)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+struct LuaFont {
+    font: ttf::Font,
+    /// "size family" of a font file which may be still loading.
+    loading: Option<String>,
+    cache: TextCache,
+}
+
+impl LuaFont {
+    /// Returns `false` while the font file is loading.
+    fn ready(&mut self) -> bool {
+        let css = if let Some(css) = &self.loading {
+            css
+        } else {
+            return true;
+        };
+
+        let loaded = emapi::emscripten::eval_js_int(&format!(
+            r"
+(() => {{
+    try {{ return document.fonts.check('{css}') ? 1 : 0; }}
+    catch (e) {{ return 1; }}
+}})()"
+        ));
+        if loaded != 0 {
+            // rendered with the fallback font
+            self.cache.clear();
+            self.loading = None;
+        }
+
+        loaded != 0
+    }
+
+    fn draw(&mut self, text: &str, x: i32, y: i32, color: Color) -> anyhow::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let target = gfx::unlocked_target()?;
+        if self.ready() {
+            self.cache
+                .get_or_render(&self.font, text, color)?
+                .blit_to(&target, x, y)
+        } else {
+            self.font.render(text, color)?.blit_to(&target, x, y)
+        }
+    }
+}
+
+fn check_text(text: &str) -> mlua::Result<()> {
+    if text.contains('\0') {
+        return Err(mlua::Error::RuntimeError(
+            "text must not contain '\\0'".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn color_arg(color: Option<mlua::Table>) -> mlua::Result<Color> {
+    let color = if let Some(color) = color {
+        color
+    } else {
+        return Ok(gfx::color());
+    };
+
+    let component = |i| -> mlua::Result<u8> {
+        let v: Option<i64> = color.get(i)?;
+        Ok(v.unwrap_or(0).clamp(0, 255) as u8)
+    };
+
+    Ok(Color {
+        r: component(1)?,
+        g: component(2)?,
+        b: component(3)?,
+    })
+}
+
+impl mlua::UserData for LuaFont {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method_mut(
+            "draw",
+            |_, this, (text, x, y, color): (String, i32, i32, Option<mlua::Table>)| {
+                check_text(&text)?;
+                let color = color_arg(color)?;
+                this.draw(&text, x, y, color).map_err(mlua::Error::external)
+            },
+        );
+        methods.add_method("measure", |_, this, text: String| {
+            check_text(&text)?;
+            this.font.size_text(&text).map_err(mlua::Error::external)
+        });
+    }
+}
+
+fn open_font(_: &mlua::Lua, (name, size): (String, i32)) -> anyhow::Result<LuaFont> {
+    anyhow::ensure!(size > 0, "invalid font size: {size}");
+    anyhow::ensure!(
+        !name.contains(['\0', '\'', '"']),
+        "{name:?}: invalid font name"
+    );
+
+    let file = appfs::jail(HOME_DIR, &name)
+        .ok()
+        .filter(|path| path.is_file());
+    let (family, loading) = match file {
+        Some(path) => {
+            let family = register_font_file(&path).map_err(|e| anyhow::anyhow!("{name}: {e}"))?;
+            let css = format!("{size}px {family}");
+            (family, Some(css))
+        }
+        None => (name, None),
+    };
+    let font = ttf::open_font(&family, size)?;
+
+    Ok(LuaFont {
+        font,
+        loading,
+        cache: TextCache::default(),
+    })
+}
+
+pub fn open(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let font = lua.create_table()?;
+
+    font.set("open", super::create_fallible_function(lua, open_font)?)?;
+
+    super::register_module(lua, "font", font)
+}
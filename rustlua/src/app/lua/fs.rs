@@ -0,0 +1,155 @@
+//! `fs` module for Lua.
+//!
+//! File operations of [crate::app::fs] and [std::fs].
+//! All paths are resolved relative to the current directory and
+//! must stay inside [HOME_DIR].
+//! Failures are returned as `nil, err`.
+
+use std::path::{Path, PathBuf};
+
+use crate::app::fs::{self as appfs, EntryType, HOME_DIR};
+
+fn jail(path: &str) -> anyhow::Result<PathBuf> {
+    appfs::jail(HOME_DIR, path)
+}
+
+/// Forbid to remove or rename the root itself.
+fn jail_not_root(path: &str) -> anyhow::Result<PathBuf> {
+    let resolved = jail(path)?;
+    anyhow::ensure!(resolved != Path::new(HOME_DIR), "{path}: Permission denied");
+
+    Ok(resolved)
+}
+
+fn type_name(etype: &EntryType) -> &'static str {
+    match etype {
+        EntryType::FILE => "file",
+        EntryType::DIR => "dir",
+    }
+}
+
+fn entry_list(lua: &mlua::Lua, list: Vec<(PathBuf, EntryType)>) -> anyhow::Result<mlua::Table> {
+    let res = lua.create_table()?;
+    for (path, etype) in list {
+        let entry = lua.create_table()?;
+        entry.set("path", path.to_string_lossy().into_owned())?;
+        entry.set("type", type_name(&etype))?;
+        res.push(entry)?;
+    }
+
+    Ok(res)
+}
+
+fn ls(lua: &mlua::Lua, dir: Option<String>) -> anyhow::Result<mlua::Table> {
+    let dir = jail(dir.as_deref().unwrap_or("."))?;
+
+    entry_list(lua, appfs::ls(dir, false)?)
+}
+
+fn ls_recursive(lua: &mlua::Lua, dir: Option<String>) -> anyhow::Result<mlua::Table> {
+    let dir = jail(dir.as_deref().unwrap_or("."))?;
+
+    entry_list(lua, appfs::ls_recursive(dir, false)?)
+}
+
+fn read(lua: &mlua::Lua, path: String) -> anyhow::Result<mlua::String> {
+    let data = std::fs::read(jail(&path)?).map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+
+    Ok(lua.create_string(data)?)
+}
+
+fn write(
+    _: &mlua::Lua,
+    (path, data, append): (String, mlua::String, Option<bool>),
+) -> anyhow::Result<bool> {
+    use std::io::Write;
+
+    let resolved = jail(&path)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append.unwrap_or(false))
+        .truncate(!append.unwrap_or(false))
+        .open(resolved)
+        .map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+    file.write_all(&data.as_bytes())
+        .map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+
+    Ok(true)
+}
+
+fn mkdir(_: &mlua::Lua, (path, parents): (String, Option<bool>)) -> anyhow::Result<bool> {
+    let resolved = jail(&path)?;
+    let res = if parents.unwrap_or(false) {
+        std::fs::create_dir_all(resolved)
+    } else {
+        std::fs::create_dir(resolved)
+    };
+    res.map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+
+    Ok(true)
+}
+
+fn remove(_: &mlua::Lua, (path, recursive): (String, Option<bool>)) -> anyhow::Result<bool> {
+    let resolved = jail_not_root(&path)?;
+    let res = if !resolved.is_dir() {
+        std::fs::remove_file(resolved)
+    } else if recursive.unwrap_or(false) {
+        std::fs::remove_dir_all(resolved)
+    } else {
+        std::fs::remove_dir(resolved)
+    };
+    res.map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+
+    Ok(true)
+}
+
+fn rename(_: &mlua::Lua, (from, to): (String, String)) -> anyhow::Result<bool> {
+    let src = jail_not_root(&from)?;
+    let dst = jail_not_root(&to)?;
+    std::fs::rename(src, dst).map_err(|e| anyhow::anyhow!("{from}: {e}"))?;
+
+    Ok(true)
+}
+
+fn exists(_: &mlua::Lua, path: String) -> anyhow::Result<bool> {
+    Ok(std::fs::exists(jail(&path)?)?)
+}
+
+fn stat(lua: &mlua::Lua, path: String) -> anyhow::Result<mlua::Table> {
+    let meta = std::fs::metadata(jail(&path)?).map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+    let etype = if meta.is_dir() {
+        EntryType::DIR
+    } else {
+        EntryType::FILE
+    };
+
+    let res = lua.create_table()?;
+    res.set("type", type_name(&etype))?;
+    res.set("size", meta.len())?;
+    if let Ok(modified) = meta.modified()
+        && let Ok(since_epoch) = modified.duration_since(std::time::UNIX_EPOCH)
+    {
+        res.set("modified", since_epoch.as_secs_f64())?;
+    }
+
+    Ok(res)
+}
+
+pub fn open(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let fs = lua.create_table()?;
+    fs.set("ls", super::create_fallible_function(lua, ls)?)?;
+    fs.set(
+        "ls_recursive",
+        super::create_fallible_function(lua, ls_recursive)?,
+    )?;
+    fs.set("read", super::create_fallible_function(lua, read)?)?;
+    fs.set("write", super::create_fallible_function(lua, write)?)?;
+    fs.set("mkdir", super::create_fallible_function(lua, mkdir)?)?;
+    fs.set("remove", super::create_fallible_function(lua, remove)?)?;
+    fs.set("rename", super::create_fallible_function(lua, rename)?)?;
+    fs.set("exists", super::create_fallible_function(lua, exists)?)?;
+    fs.set("stat", super::create_fallible_function(lua, stat)?)?;
+
+    super::register_module(lua, "fs", fs)
+}
@@ -0,0 +1,386 @@
+//! `gfx` module for Lua: 2D drawing to the video surface.
+//!
+//! Rectangles are drawn by `SDL_FillRect()` and other shapes by writing
+//! pixels, taking the pixel format and pitch of the surface into account.
+//! Emscripten SDL copies the canvas into the pixel buffer on lock and
+//! back on unlock, and fill/blit must not be done while locked,
+//! so the surface is locked lazily only for pixel access
+//! and unlocked by [finish()] (or by the next fill/blit).
+
+use std::cell::Cell;
+use std::mem::ManuallyDrop;
+
+use crate::emapi::sdl::{self, Color, Rect, Surface};
+
+thread_local! {
+    static COLOR: Cell<Color> = const { Cell::new(Color::WHITE) };
+    static LOCKED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn target() -> mlua::Result<ManuallyDrop<Surface>> {
+    sdl::get_video_surface()
+        .ok_or_else(|| mlua::Error::RuntimeError("gfx: video mode is not set".to_string()))
+}
+
+/// Get the video surface for fill/blit operations.
+pub fn unlocked_target() -> mlua::Result<ManuallyDrop<Surface>> {
+    let surface = target()?;
+    if LOCKED.replace(false) {
+        surface.unlock();
+    }
+
+    Ok(surface)
+}
+
+/// Pixel access to a locked surface.
+struct Pixels {
+    surface: ManuallyDrop<Surface>,
+    width: i32,
+    height: i32,
+    pitch: usize,
+    bpp: usize,
+}
+
+impl Pixels {
+    fn new() -> mlua::Result<Self> {
+        let surface = target()?;
+        if !LOCKED.get() {
+            surface.lock().map_err(mlua::Error::external)?;
+            LOCKED.set(true);
+        }
+
+        Ok(Self {
+            width: surface.width(),
+            height: surface.height(),
+            pitch: surface.pitch() as usize,
+            bpp: surface.bytes_per_pixel(),
+            surface,
+        })
+    }
+
+    fn offset(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+
+        Some(y as usize * self.pitch + x as usize * self.bpp)
+    }
+
+    fn set(&self, x: i32, y: i32, pixel: u32) {
+        if let Some(offset) = self.offset(x, y) {
+            let bytes = pixel.to_le_bytes();
+            self.surface.pixels()[offset..offset + self.bpp].copy_from_slice(&bytes[..self.bpp]);
+        }
+    }
+
+    fn get(&self, x: i32, y: i32) -> Option<Color> {
+        let offset = self.offset(x, y)?;
+        let mut bytes = [0u8; 4];
+        bytes[..self.bpp].copy_from_slice(&self.surface.pixels()[offset..offset + self.bpp]);
+
+        Some(self.surface.get_rgb(u32::from_le_bytes(bytes)))
+    }
+
+    fn hline(&self, x1: i64, x2: i64, y: i64, pixel: u32) {
+        if y < 0 || y >= self.height.into() {
+            return;
+        }
+        for x in x1.max(0)..=x2.min(i64::from(self.width) - 1) {
+            self.set(x as i32, y as i32, pixel);
+        }
+    }
+}
+
+/// Current draw color.
+pub fn color() -> Color {
+    COLOR.with(Cell::get)
+}
+
+/// Unlock the video surface if locked by pixel operations.
+/// Call before flip.
+pub fn finish() {
+    if let Some(surface) = sdl::get_video_surface()
+        && LOCKED.replace(false)
+    {
+        surface.unlock();
+    }
+}
+
+fn to_u8(v: i64) -> u8 {
+    v.clamp(0, 255) as u8
+}
+
+/// `r, g, b` or the current color if all omitted.
+fn color_arg(rgb: (Option<i64>, Option<i64>, Option<i64>)) -> Color {
+    match rgb {
+        (None, None, None) => COLOR.with(Cell::get),
+        (r, g, b) => Color {
+            r: to_u8(r.unwrap_or(0)),
+            g: to_u8(g.unwrap_or(0)),
+            b: to_u8(b.unwrap_or(0)),
+        },
+    }
+}
+
+#[derive(PartialEq)]
+enum DrawMode {
+    Fill,
+    Line,
+}
+
+fn draw_mode(mode: &str) -> mlua::Result<DrawMode> {
+    match mode {
+        "fill" => Ok(DrawMode::Fill),
+        "line" => Ok(DrawMode::Line),
+        _ => Err(mlua::Error::RuntimeError(format!(
+            "bad draw mode '{mode}' (expected 'fill' or 'line')"
+        ))),
+    }
+}
+
+/// Clip to the surface because `SDL_Rect` has a limited range.
+/// (`i64` so that `x + w` does not overflow.)
+fn fill_rect(surface: &Surface, x: i64, y: i64, w: i64, h: i64, color: Color) -> mlua::Result<()> {
+    let x1 = x.max(0);
+    let y1 = y.max(0);
+    let x2 = (x + w).min(surface.width().into());
+    let y2 = (y + h).min(surface.height().into());
+    if x1 >= x2 || y1 >= y2 {
+        return Ok(());
+    }
+
+    let rect = Rect {
+        x: x1 as i32,
+        y: y1 as i32,
+        w: (x2 - x1) as u32,
+        h: (y2 - y1) as u32,
+    };
+    surface
+        .fill_rect(rect, color)
+        .map_err(mlua::Error::external)
+}
+
+fn rectangle(mode: &str, x: i32, y: i32, w: i32, h: i32) -> mlua::Result<()> {
+    let mode = draw_mode(mode)?;
+    let color = COLOR.with(Cell::get);
+    let surface = unlocked_target()?;
+    let (x, y, w, h) = (i64::from(x), i64::from(y), i64::from(w), i64::from(h));
+
+    if mode == DrawMode::Fill || w <= 2 || h <= 2 {
+        fill_rect(&surface, x, y, w, h, color)
+    } else {
+        fill_rect(&surface, x, y, w, 1, color)?;
+        fill_rect(&surface, x, y + h - 1, w, 1, color)?;
+        fill_rect(&surface, x, y + 1, 1, h - 2, color)?;
+        fill_rect(&surface, x + w - 1, y + 1, 1, h - 2, color)
+    }
+}
+
+/// Cohen–Sutherland line clipping to `[0, width) x [0, height)`.
+/// `None` if the line is entirely outside.
+fn clip_line(
+    (mut x1, mut y1): (f64, f64),
+    (mut x2, mut y2): (f64, f64),
+    width: f64,
+    height: f64,
+) -> Option<((i64, i64), (i64, i64))> {
+    const LEFT: u8 = 1;
+    const RIGHT: u8 = 2;
+    const TOP: u8 = 4;
+    const BOTTOM: u8 = 8;
+    let (xmax, ymax) = (width - 1.0, height - 1.0);
+    let outcode = |x: f64, y: f64| {
+        let mut code = 0;
+        if x < 0.0 {
+            code |= LEFT;
+        } else if x > xmax {
+            code |= RIGHT;
+        }
+        if y < 0.0 {
+            code |= TOP;
+        } else if y > ymax {
+            code |= BOTTOM;
+        }
+        code
+    };
+
+    let (mut code1, mut code2) = (outcode(x1, y1), outcode(x2, y2));
+    loop {
+        if code1 | code2 == 0 {
+            let round = |x: f64, y: f64| (x.round() as i64, y.round() as i64);
+            return Some((round(x1, y1), round(x2, y2)));
+        }
+        if code1 & code2 != 0 {
+            return None;
+        }
+
+        // move the endpoint outside to the edge
+        let code = if code1 != 0 { code1 } else { code2 };
+        let (x, y) = if code & TOP != 0 {
+            (x1 + (x2 - x1) * (0.0 - y1) / (y2 - y1), 0.0)
+        } else if code & BOTTOM != 0 {
+            (x1 + (x2 - x1) * (ymax - y1) / (y2 - y1), ymax)
+        } else if code & LEFT != 0 {
+            (0.0, y1 + (y2 - y1) * (0.0 - x1) / (x2 - x1))
+        } else {
+            (xmax, y1 + (y2 - y1) * (xmax - x1) / (x2 - x1))
+        };
+        if code == code1 {
+            (x1, y1) = (x, y);
+            code1 = outcode(x1, y1);
+        } else {
+            (x2, y2) = (x, y);
+            code2 = outcode(x2, y2);
+        }
+    }
+}
+
+/// Bresenham's line algorithm (after clipping, so that the loop is
+/// bounded by the surface size).
+fn line(x1: i32, y1: i32, x2: i32, y2: i32) -> mlua::Result<()> {
+    let pixels = Pixels::new()?;
+    let pixel = pixels.surface.map_rgb(COLOR.with(Cell::get));
+    let Some(((x1, y1), (x2, y2))) = clip_line(
+        (x1.into(), y1.into()),
+        (x2.into(), y2.into()),
+        pixels.width.into(),
+        pixels.height.into(),
+    ) else {
+        return Ok(());
+    };
+
+    let dx = (x2 - x1).abs();
+    let dy = -(y2 - y1).abs();
+    let sx = if x1 < x2 { 1 } else { -1 };
+    let sy = if y1 < y2 { 1 } else { -1 };
+    let mut err = dx + dy;
+    let (mut x, mut y) = (x1, y1);
+    loop {
+        pixels.set(x as i32, y as i32, pixel);
+        if x == x2 && y == y2 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+
+    Ok(())
+}
+
+/// Scanline circle: only the rows on the surface are visited.
+/// A row `d` away from the center spans `|x| <= extent(d)`, where
+/// `x² + d² <= r² + r` as in the midpoint circle algorithm.
+fn circle(mode: &str, cx: i32, cy: i32, r: i32) -> mlua::Result<()> {
+    let mode = draw_mode(mode)?;
+    if r < 0 {
+        return Ok(());
+    }
+    let pixels = Pixels::new()?;
+    let pixel = pixels.surface.map_rgb(COLOR.with(Cell::get));
+    let (cx, cy, r) = (i64::from(cx), i64::from(cy), i64::from(r));
+
+    // -1 outside of the circle
+    let extent = |d: i64| {
+        let v = r * r + r - d * d;
+        if v < 0 { -1 } else { v.isqrt() }
+    };
+    let top = (cy - r).max(0);
+    let bottom = (cy + r).min(i64::from(pixels.height) - 1);
+    for y in top..=bottom {
+        let d = (y - cy).abs();
+        let outer = extent(d);
+        if mode == DrawMode::Fill {
+            pixels.hline(cx - outer, cx + outer, y, pixel);
+        } else {
+            // from the extent of the next row outwards
+            let inner = (extent(d + 1) + 1).min(outer);
+            pixels.hline(cx - outer, cx - inner, y, pixel);
+            pixels.hline(cx + inner, cx + outer, y, pixel);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn open(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let gfx = lua.create_table()?;
+
+    gfx.set("width", lua.create_function(|_, ()| Ok(target()?.width()))?)?;
+    gfx.set(
+        "height",
+        lua.create_function(|_, ()| Ok(target()?.height()))?,
+    )?;
+    gfx.set(
+        "setColor",
+        lua.create_function(|_, (r, g, b): (i64, i64, i64)| {
+            COLOR.with(|c| {
+                c.set(Color {
+                    r: to_u8(r),
+                    g: to_u8(g),
+                    b: to_u8(b),
+                })
+            });
+            Ok(())
+        })?,
+    )?;
+    gfx.set(
+        "getColor",
+        lua.create_function(|_, ()| {
+            let c = COLOR.with(Cell::get);
+            Ok((c.r, c.g, c.b))
+        })?,
+    )?;
+    gfx.set(
+        "clear",
+        lua.create_function(|_, rgb: (Option<i64>, Option<i64>, Option<i64>)| {
+            let color = match rgb {
+                (None, None, None) => Color::BLACK,
+                rgb => color_arg(rgb),
+            };
+            unlocked_target()?
+                .fill(color)
+                .map_err(mlua::Error::external)
+        })?,
+    )?;
+    gfx.set(
+        "getPixel",
+        lua.create_function(|_, (x, y): (i32, i32)| {
+            let pixels = Pixels::new()?;
+            Ok(pixels.get(x, y).map(|c| (c.r, c.g, c.b)))
+        })?,
+    )?;
+    gfx.set(
+        "setPixel",
+        lua.create_function(
+            |_, (x, y, r, g, b): (i32, i32, Option<i64>, Option<i64>, Option<i64>)| {
+                let pixels = Pixels::new()?;
+                let pixel = pixels.surface.map_rgb(color_arg((r, g, b)));
+                pixels.set(x, y, pixel);
+                Ok(())
+            },
+        )?,
+    )?;
+    gfx.set(
+        "rectangle",
+        lua.create_function(|_, (mode, x, y, w, h): (String, i32, i32, i32, i32)| {
+            rectangle(&mode, x, y, w, h)
+        })?,
+    )?;
+    gfx.set(
+        "line",
+        lua.create_function(|_, (x1, y1, x2, y2): (i32, i32, i32, i32)| line(x1, y1, x2, y2))?,
+    )?;
+    gfx.set(
+        "circle",
+        lua.create_function(|_, (mode, x, y, r): (String, i32, i32, i32)| circle(&mode, x, y, r))?,
+    )?;
+
+    super::register_module(lua, "gfx", gfx)
+}
@@ -0,0 +1,69 @@
+//! `image` module for Lua.
+//!
+//! `image.load(path)` loads a PNG/JPG file (e.g. imported by the file
+//! import button) and returns an image object:
+//!
+//! * `img:draw(x, y)` draws the whole image to the video surface.
+//! * `img:draw(x, y, sx, sy, sw, sh)` draws a part of it (sprite sheets).
+//! * `img:width()`, `img:height()`
+//!
+//! Paths are resolved like the `fs` module.
+
+use crate::app::fs::{self as appfs, HOME_DIR};
+use crate::emapi::sdl::{self, Rect, Surface};
+
+use super::gfx;
+
+struct Image(Surface);
+
+/// `x, y[, sx, sy, sw, sh]`
+type DrawArgs = (i32, i32, Option<i32>, Option<i32>, Option<u32>, Option<u32>);
+
+impl mlua::UserData for Image {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("width", |_, this, ()| Ok(this.0.width()));
+        methods.add_method("height", |_, this, ()| Ok(this.0.height()));
+        methods.add_method("draw", |_, this, args: DrawArgs| {
+            let (x, y, sx, sy, sw, sh) = args;
+            let target = gfx::unlocked_target()?;
+            let res = match (sx, sy, sw, sh) {
+                (None, None, None, None) => this.0.blit_to(&target, x, y),
+                (Some(sx), Some(sy), Some(sw), Some(sh)) => {
+                    let rect = Rect {
+                        x: sx,
+                        y: sy,
+                        w: sw,
+                        h: sh,
+                    };
+                    this.0.blit_rect_to(rect, &target, x, y)
+                }
+                _ => {
+                    return Err(mlua::Error::RuntimeError(
+                        "draw: source rectangle needs sx, sy, sw and sh".to_string(),
+                    ));
+                }
+            };
+            res.map_err(mlua::Error::external)
+        });
+        methods.add_meta_method(mlua::MetaMethod::ToString, |_, this, ()| {
+            Ok(format!("image ({}x{})", this.0.width(), this.0.height()))
+        });
+    }
+}
+
+fn load(_: &mlua::Lua, path: String) -> anyhow::Result<Image> {
+    anyhow::ensure!(!path.contains('\0'), "{path:?}: invalid path");
+    let resolved = appfs::jail(HOME_DIR, &path)?;
+    let surface = sdl::image::load(&resolved.to_string_lossy())
+        .map_err(|e| anyhow::anyhow!("{path}: {e:#}"))?;
+
+    Ok(Image(surface))
+}
+
+pub fn open(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let image = lua.create_table()?;
+
+    image.set("load", super::create_fallible_function(lua, load)?)?;
+
+    super::register_module(lua, "image", image)
+}
@@ -0,0 +1,106 @@
+//! `input` module for Lua: keyboard and mouse state.
+//!
+//! * `input.isDown(key)`: `true` while the key is held down.
+//! * `input.pressed(key)`: `true` only on the frame the key went down.
+//! * `input.mouse()`: mouse position (x, y).
+//! * `input.mouseDown(button)`: 1: left, 2: middle, 3: right
+//!
+//! Key names are those of [event::key_name()] (e.g. "a", "left", "space").
+//! The state is updated by [begin_frame()] from the main loop.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use crate::emapi::sdl::event::{self, Event};
+
+#[derive(Default)]
+struct State {
+    down: HashSet<i32>,
+    pressed: HashSet<i32>,
+    /// Presses of this frame not yet passed to `keypressed()`
+    unreported: HashSet<i32>,
+    mouse: (i32, i32),
+    buttons: HashSet<u8>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// Update the state with the events of this frame.
+pub fn begin_frame(events: &[Event]) {
+    STATE.with(|cell| {
+        let mut state = cell.borrow_mut();
+        state.pressed.clear();
+        state.unreported.clear();
+        for event in events {
+            match *event {
+                Event::KeyDown { sym, .. } => {
+                    // ignore key repeat
+                    if state.down.insert(sym) {
+                        state.pressed.insert(sym);
+                        state.unreported.insert(sym);
+                    }
+                }
+                Event::KeyUp { sym, .. } => {
+                    state.down.remove(&sym);
+                }
+                Event::MouseMotion { x, y, .. } => state.mouse = (x, y),
+                Event::MouseButtonDown { button, x, y } => {
+                    state.buttons.insert(button);
+                    state.mouse = (x, y);
+                }
+                Event::MouseButtonUp { button, x, y } => {
+                    state.buttons.remove(&button);
+                    state.mouse = (x, y);
+                }
+                Event::Quit | Event::Resize { .. } => {}
+            }
+        }
+    });
+}
+
+/// `true` if the key-down event is not a key repeat.
+/// Valid after [begin_frame()] of the frame.
+pub fn is_pressed(sym: i32) -> bool {
+    STATE.with(|cell| cell.borrow().pressed.contains(&sym))
+}
+
+/// `true` only for the first call for a key newly pressed in this frame,
+/// so that key repeats do not call `keypressed()` again.
+pub fn take_press(sym: i32) -> bool {
+    STATE.with(|cell| cell.borrow_mut().unreported.remove(&sym))
+}
+
+fn key_arg(name: &str) -> mlua::Result<i32> {
+    event::key_from_name(name)
+        .ok_or_else(|| mlua::Error::RuntimeError(format!("unknown key: '{name}'")))
+}
+
+pub fn open(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let input = lua.create_table()?;
+
+    input.set(
+        "isDown",
+        lua.create_function(|_, name: String| {
+            let sym = key_arg(&name)?;
+            Ok(STATE.with(|cell| cell.borrow().down.contains(&sym)))
+        })?,
+    )?;
+    input.set(
+        "pressed",
+        lua.create_function(|_, name: String| Ok(is_pressed(key_arg(&name)?)))?,
+    )?;
+    input.set(
+        "mouse",
+        lua.create_function(|_, ()| Ok(STATE.with(|cell| cell.borrow().mouse)))?,
+    )?;
+    input.set(
+        "mouseDown",
+        lua.create_function(|_, button: u8| {
+            Ok(STATE.with(|cell| cell.borrow().buttons.contains(&button)))
+        })?,
+    )?;
+
+    super::register_module(lua, "input", input)
+}
@@ -0,0 +1,235 @@
+//! `json` module for Lua backed by [serde_json].
+//!
+//! * `json.encode(value[, {pretty = bool}])`
+//! * `json.decode(str)`
+//! * `json.null`: sentinel for JSON null (`nil` cannot be a table value)
+//! * `json.array(t)`: mark `t` as an array (so that `{}` encodes as `[]`)
+//!
+//! A table is encoded as an array if its keys are exactly `1..n`
+//! (or it is marked), otherwise as an object with sorted keys.
+//! Decoded arrays are marked, so they are encoded back as arrays.
+//! Errors show the failing path such as `$.items[2].name`
+//! (array indices are those of Lua).
+
+/// Nesting limit (same as the parser of serde_json).
+const MAX_DEPTH: usize = 128;
+
+/// Registry key of the metatable which marks arrays.
+const ARRAY_MT_KEY: &str = "json.array";
+
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Path from the root to the current value.
+#[derive(Default)]
+struct Path(Vec<Segment>);
+
+impl std::fmt::Display for Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "$")?;
+        for seg in &self.0 {
+            match seg {
+                Segment::Key(key)
+                    if !key.is_empty()
+                        && !key.starts_with(|c: char| c.is_ascii_digit())
+                        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+                {
+                    write!(f, ".{key}")?
+                }
+                Segment::Key(key) => write!(f, "[{key:?}]")?,
+                Segment::Index(i) => write!(f, "[{i}]")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn json_error(func: &str, msg: impl std::fmt::Display, path: &Path) -> mlua::Error {
+    mlua::Error::RuntimeError(format!("json.{func}: {msg} at {path}"))
+}
+
+struct Encoder {
+    array_mt: mlua::Table,
+    path: Path,
+    /// Pointers of the tables being encoded (to detect cycles)
+    visiting: Vec<usize>,
+}
+
+impl Encoder {
+    fn error(&self, msg: impl std::fmt::Display) -> mlua::Error {
+        json_error("encode", msg, &self.path)
+    }
+
+    fn encode(&mut self, value: &mlua::Value) -> mlua::Result<serde_json::Value> {
+        use serde_json::Value as J;
+
+        match value {
+            mlua::Value::Nil => Ok(J::Null),
+            v if *v == mlua::Value::NULL => Ok(J::Null),
+            mlua::Value::Boolean(b) => Ok(J::Bool(*b)),
+            mlua::Value::Integer(n) => Ok(J::from(*n)),
+            mlua::Value::Number(n) => serde_json::Number::from_f64(*n)
+                .map(J::Number)
+                .ok_or_else(|| self.error(format!("cannot encode {n}"))),
+            mlua::Value::String(s) => match s.to_str() {
+                Ok(s) => Ok(J::String(s.to_string())),
+                Err(_) => Err(self.error("string is not valid UTF-8")),
+            },
+            mlua::Value::Table(t) => self.encode_table(t),
+            v => Err(self.error(format!("cannot encode {}", v.type_name()))),
+        }
+    }
+
+    fn encode_table(&mut self, table: &mlua::Table) -> mlua::Result<serde_json::Value> {
+        let ptr = table.to_pointer() as usize;
+        if self.visiting.contains(&ptr) {
+            return Err(self.error("circular reference"));
+        }
+        if self.visiting.len() >= MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+
+        self.visiting.push(ptr);
+        let res = self.encode_entries(table);
+        self.visiting.pop();
+
+        res
+    }
+
+    fn encode_entries(&mut self, table: &mlua::Table) -> mlua::Result<serde_json::Value> {
+        let mut entries = Vec::new();
+        for pair in table.pairs::<mlua::Value, mlua::Value>() {
+            entries.push(pair?);
+        }
+
+        let marked = table.metatable().is_some_and(|mt| mt == self.array_mt);
+        let len = entries.len();
+        let is_array = marked
+            || (len > 0
+                && entries
+                    .iter()
+                    .all(|(k, _)| k.as_integer().is_some_and(|i| i >= 1 && i as usize <= len)));
+
+        if is_array {
+            entries.sort_by_key(|(k, _)| k.as_integer().unwrap_or(0));
+            let mut array = Vec::with_capacity(len);
+            for (key, value) in &entries {
+                let index = match key.as_integer() {
+                    Some(i) if i as usize == array.len() + 1 => i as usize,
+                    _ => return Err(self.error("array has non-sequential keys")),
+                };
+                self.path.0.push(Segment::Index(index));
+                let v = self.encode(value)?;
+                self.path.0.pop();
+                array.push(v);
+            }
+            return Ok(serde_json::Value::Array(array));
+        }
+
+        let mut object = serde_json::Map::new();
+        for (key, value) in &entries {
+            let key = match key {
+                mlua::Value::String(s) => match s.to_str() {
+                    Ok(s) => s.to_string(),
+                    Err(_) => return Err(self.error("key is not valid UTF-8")),
+                },
+                mlua::Value::Integer(n) => n.to_string(),
+                k => return Err(self.error(format!("cannot use {} as a key", k.type_name()))),
+            };
+            self.path.0.push(Segment::Key(key.clone()));
+            let v = self.encode(value)?;
+            self.path.0.pop();
+            object.insert(key, v);
+        }
+
+        Ok(serde_json::Value::Object(object))
+    }
+}
+
+fn decode(
+    lua: &mlua::Lua,
+    array_mt: &mlua::Table,
+    value: serde_json::Value,
+) -> mlua::Result<mlua::Value> {
+    use serde_json::Value as J;
+
+    Ok(match value {
+        J::Null => mlua::Value::NULL,
+        J::Bool(b) => mlua::Value::Boolean(b),
+        J::Number(n) => match n.as_i64() {
+            Some(i) => mlua::Value::Integer(i),
+            None => mlua::Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        J::String(s) => mlua::Value::String(lua.create_string(s)?),
+        J::Array(array) => {
+            let table = lua.create_table_with_capacity(array.len(), 0)?;
+            for v in array {
+                table.raw_push(decode(lua, array_mt, v)?)?;
+            }
+            table.set_metatable(Some(array_mt.clone()))?;
+            mlua::Value::Table(table)
+        }
+        J::Object(object) => {
+            let table = lua.create_table_with_capacity(0, object.len())?;
+            for (k, v) in object {
+                table.raw_set(k, decode(lua, array_mt, v)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+    })
+}
+
+fn array_mt(lua: &mlua::Lua) -> mlua::Result<mlua::Table> {
+    lua.named_registry_value(ARRAY_MT_KEY)
+}
+
+pub fn open(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let json = lua.create_table()?;
+
+    let mt = lua.create_table()?;
+    mt.set("__name", "json.array")?;
+    lua.set_named_registry_value(ARRAY_MT_KEY, mt)?;
+
+    json.set("null", mlua::Value::NULL)?;
+    json.set(
+        "encode",
+        lua.create_function(|lua, (value, opts): (mlua::Value, Option<mlua::Table>)| {
+            let pretty = match opts {
+                Some(opts) => opts.get::<Option<bool>>("pretty")?.unwrap_or(false),
+                None => false,
+            };
+            let mut encoder = Encoder {
+                array_mt: array_mt(lua)?,
+                path: Path::default(),
+                visiting: Vec::new(),
+            };
+            let value = encoder.encode(&value)?;
+            let res = if pretty {
+                serde_json::to_string_pretty(&value)
+            } else {
+                serde_json::to_string(&value)
+            };
+            res.map_err(|e| json_error("encode", e, &Path::default()))
+        })?,
+    )?;
+    json.set(
+        "decode",
+        lua.create_function(|lua, s: mlua::String| {
+            let value: serde_json::Value = serde_json::from_slice(&s.as_bytes())
+                .map_err(|e| mlua::Error::RuntimeError(format!("json.decode: {e}")))?;
+            decode(lua, &array_mt(lua)?, value)
+        })?,
+    )?;
+    json.set(
+        "array",
+        lua.create_function(|lua, table: mlua::Table| {
+            table.set_metatable(Some(array_mt(lua)?))?;
+            Ok(table)
+        })?,
+    )?;
+
+    super::register_module(lua, "json", json)
+}
@@ -0,0 +1,177 @@
+//! Instruction budget and wall-clock timeout for Lua execution.
+//!
+//! Lua code runs synchronously in the browser main thread,
+//! so an infinite loop would freeze the page.
+//! A count hook checks the budget every [HOOK_PERIOD] instructions and
+//! raises [LimitExceeded] to abort the running chunk.
+//! The state is still usable after that.
+//! The hook also samples the peak memory usage, and yields a thread
+//! resumed with a time slice (background jobs) when the slice is used up.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::emapi;
+
+const HOOK_PERIOD: u32 = 1000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_instructions: Option<u64>,
+    pub timeout_ms: Option<f64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_instructions: Some(200_000_000),
+            timeout_ms: Some(5000.0),
+        }
+    }
+}
+
+/// Error object raised from the hook.
+#[derive(Debug)]
+pub enum LimitExceeded {
+    Instructions(u64),
+    Timeout(f64),
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Instructions(max) => {
+                write!(f, "execution limit exceeded: more than {max} instructions")
+            }
+            Self::Timeout(ms) => write!(f, "execution limit exceeded: timeout ({ms} ms)"),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+#[derive(Clone, Copy, Default)]
+struct Budget {
+    /// `None` while no limit is armed.
+    limits: Option<Limits>,
+    used: u64,
+    started_at: f64,
+}
+
+#[derive(Clone, Copy)]
+struct Slice {
+    /// Pointer of the thread
+    thread: usize,
+    deadline: f64,
+}
+
+/// Shared between the hook and the session.
+#[derive(Default)]
+pub struct Monitor {
+    budget: Cell<Budget>,
+    peak_memory: Cell<usize>,
+    slice: Cell<Option<Slice>>,
+}
+
+impl Monitor {
+    /// Update the peak memory usage with the current value.
+    pub fn sample_memory(&self, lua: &mlua::Lua) -> usize {
+        let peak = self.peak_memory.get().max(lua.used_memory());
+        self.peak_memory.set(peak);
+
+        peak
+    }
+}
+
+/// Install the count hook to all threads of `lua`.
+pub fn install(lua: &mlua::Lua) -> anyhow::Result<Rc<Monitor>> {
+    let monitor = Rc::new(Monitor::default());
+
+    let hook_monitor = monitor.clone();
+    let triggers = mlua::HookTriggers::new().every_nth_instruction(HOOK_PERIOD);
+    lua.set_global_hook(triggers, move |lua, _| {
+        hook_monitor.sample_memory(lua);
+
+        if let Some(slice) = hook_monitor.slice.get()
+            && emapi::emscripten::performance_now() > slice.deadline
+            && lua.current_thread().to_pointer() as usize == slice.thread
+        {
+            return Ok(mlua::VmState::Yield);
+        }
+
+        let mut budget = hook_monitor.budget.get();
+        let limits = if let Some(limits) = budget.limits {
+            limits
+        } else {
+            return Ok(mlua::VmState::Continue);
+        };
+
+        budget.used += HOOK_PERIOD as u64;
+        hook_monitor.budget.set(budget);
+
+        if let Some(max) = limits.max_instructions
+            && budget.used > max
+        {
+            return Err(mlua::Error::external(LimitExceeded::Instructions(max)));
+        }
+        if let Some(timeout) = limits.timeout_ms
+            && emapi::emscripten::performance_now() - budget.started_at > timeout
+        {
+            return Err(mlua::Error::external(LimitExceeded::Timeout(timeout)));
+        }
+
+        Ok(mlua::VmState::Continue)
+    })?;
+
+    Ok(monitor)
+}
+
+/// Restores the previous budget on drop.
+pub struct Guard {
+    monitor: Rc<Monitor>,
+    prev: Budget,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.monitor.budget.set(self.prev);
+    }
+}
+
+/// Start a new budget until the returned [Guard] is dropped.
+pub fn arm(monitor: &Rc<Monitor>, limits: Limits) -> Guard {
+    let prev = monitor.budget.replace(Budget {
+        limits: Some(limits),
+        used: 0,
+        started_at: emapi::emscripten::performance_now(),
+    });
+
+    Guard {
+        monitor: monitor.clone(),
+        prev,
+    }
+}
+
+/// Clears the time slice on drop.
+pub struct SliceGuard {
+    monitor: Rc<Monitor>,
+}
+
+impl Drop for SliceGuard {
+    fn drop(&mut self) {
+        self.monitor.slice.set(None);
+    }
+}
+
+/// Make the hook yield `thread` after `ms` milliseconds
+/// until the returned [SliceGuard] is dropped.
+pub fn time_slice(monitor: &Rc<Monitor>, thread: &mlua::Thread, ms: f64) -> SliceGuard {
+    monitor.slice.set(Some(Slice {
+        thread: thread.to_pointer() as usize,
+        deadline: emapi::emscripten::performance_now() + ms,
+    }));
+
+    SliceGuard {
+        monitor: monitor.clone(),
+    }
+}
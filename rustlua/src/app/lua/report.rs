@@ -0,0 +1,108 @@
+//! Structured Lua error reports.
+//!
+//! mlua puts the traceback into the error text
+//! (`"<message>\nstack traceback:\n\t..."`), so it is split here and
+//! the source location is taken from `chunk:line:` in the message or,
+//! for errors raised in Rust code, from the first Lua frame of the
+//! traceback.
+
+use crate::emapi;
+
+const TRACEBACK_HEADER: &str = "stack traceback:";
+
+#[derive(Debug, serde::Serialize)]
+pub struct ErrorReport {
+    pub message: String,
+    /// Chunk name as shown in messages (without '=' or '@').
+    pub chunk: Option<String>,
+    pub line: Option<u32>,
+    pub traceback: Option<String>,
+}
+
+impl std::fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Lua error: {}", self.message)?;
+        if let (Some(chunk), Some(line)) = (&self.chunk, self.line) {
+            write!(f, "\n  --> {chunk}:{line}")?;
+        }
+        if let Some(traceback) = &self.traceback {
+            write!(f, "\n{traceback}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ErrorReport {
+    pub fn new(err: &anyhow::Error) -> Self {
+        let text = format!("{err:#}");
+        let (message, traceback) = match text.find(TRACEBACK_HEADER) {
+            Some(pos) => (
+                text[..pos].trim_end().to_string(),
+                Some(text[pos..].trim_end().to_string()),
+            ),
+            None => (text.trim_end().to_string(), None),
+        };
+
+        let location = find_location(&message).or_else(|| {
+            traceback
+                .as_deref()
+                .and_then(|tb| tb.lines().skip(1).find_map(find_location))
+        });
+        let (chunk, line) = match location {
+            Some((chunk, line)) => (Some(chunk), Some(line)),
+            None => (None, None),
+        };
+
+        Self {
+            message,
+            chunk,
+            line,
+            traceback,
+        }
+    }
+}
+
+/// Find the first `chunk:line:` in `text`.
+fn find_location(text: &str) -> Option<(String, u32)> {
+    for line in text.lines() {
+        let line = line.trim_start();
+        for (pos, _) in line.match_indices(':') {
+            let rest = &line[pos + 1..];
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 || !rest[digits..].starts_with(':') {
+                continue;
+            }
+            // remove "runtime error: " etc.
+            let chunk = line[..pos].rsplit(": ").next().unwrap_or_default();
+            if chunk.is_empty() || chunk == "[C]" {
+                continue;
+            }
+            if let Ok(lineno) = rest[..digits].parse() {
+                return Some((chunk.to_string(), lineno));
+            }
+        }
+    }
+
+    None
+}
+
+/// Print the report to stderr and pass it to `Module.onLuaError()`
+/// (if defined) so that the page can show the location.
+pub fn report(err: &anyhow::Error) -> ErrorReport {
+    let report = ErrorReport::new(err);
+    eprintln!("{report}");
+
+    match serde_json::to_string(&report) {
+        Ok(json) => emapi::emscripten::eval_js(&format!(
+            r"
+(() => {{
+    try {{ if (Module.onLuaError) Module.onLuaError({json}); }}
+    catch (e) {{ console.error(e); }}
+}})()"
+        )),
+        Err(err) => log::error!("{err}"),
+    }
+
+    report
+}
@@ -0,0 +1,202 @@
+//! Restrict file access of the standard libraries to a root directory.
+//!
+//! * Paths given to `io.open`, `io.lines`, `io.input`, `io.output`,
+//!   `os.remove`, `os.rename`, `loadfile` and `dofile` are resolved
+//!   relative to the current directory and must stay inside the root.
+//! * `os.tmpname` returns a new file in `<root>/tmp`.
+//! * `require` and `package.searchpath` only find Lua files inside the
+//!   root or the module search roots, whatever `package.path` is.
+//! * `io.popen`, `os.execute` and C modules are blocked.
+//!
+//! Violations raise errors (not `nil, err`) so that they are never
+//! overlooked.
+
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+
+use crate::app::fs::jail;
+
+fn sandbox_error(msg: impl std::fmt::Display) -> mlua::Error {
+    mlua::Error::RuntimeError(format!("sandbox: {msg}"))
+}
+
+/// Replace `table[name]` with a function which checks and rewrites
+/// string arguments at `positions` before calling the original one.
+fn wrap_paths(
+    lua: &mlua::Lua,
+    table: &mlua::Table,
+    name: &str,
+    positions: &'static [usize],
+    root: &Path,
+) -> anyhow::Result<()> {
+    let orig = if let Ok(orig) = table.get::<mlua::Function>(name) {
+        orig
+    } else {
+        return Ok(());
+    };
+
+    let root = root.to_path_buf();
+    let wrapped = lua.create_function(move |lua, mut args: mlua::MultiValue| {
+        for &pos in positions {
+            let resolved = match args.get(pos) {
+                Some(mlua::Value::String(path)) => Some(
+                    jail(&root, &*path.to_str()?).map_err(|e| sandbox_error(format!("{e:#}")))?,
+                ),
+                _ => None,
+            };
+            if let Some(resolved) = resolved {
+                let resolved = lua.create_string(resolved.to_string_lossy().as_bytes())?;
+                args[pos] = mlua::Value::String(resolved);
+            }
+        }
+        orig.call::<mlua::MultiValue>(args)
+    })?;
+    table.set(name, wrapped)?;
+
+    Ok(())
+}
+
+fn block(lua: &mlua::Lua, table: &mlua::Table, libname: &str, name: &str) -> anyhow::Result<()> {
+    if !table.contains_key(name)? {
+        return Ok(());
+    }
+
+    let fullname = format!("{libname}{name}");
+    let blocked = lua.create_function(move |_, _: mlua::MultiValue| -> mlua::Result<()> {
+        Err(sandbox_error(format!("{fullname} is not allowed")))
+    })?;
+    table.set(name, blocked)?;
+
+    Ok(())
+}
+
+fn tmpname(lua: &mlua::Lua, os: &mlua::Table, root: &Path) -> anyhow::Result<()> {
+    let dir = root.join("tmp");
+    let count = Cell::new(0u32);
+    let func = lua.create_function(move |_, _: mlua::MultiValue| {
+        std::fs::create_dir_all(&dir).map_err(|e| sandbox_error(format!("tmpname: {e}")))?;
+        loop {
+            count.set(count.get() + 1);
+            let path: PathBuf = dir.join(format!("lua_{:06}", count.get()));
+            // create an empty file like mkstemp()
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(path.to_string_lossy().into_owned()),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(sandbox_error(format!("tmpname: {e}"))),
+            }
+        }
+    })?;
+    os.set("tmpname", func)?;
+
+    Ok(())
+}
+
+/// Like `package.searchpath`, but templates resolving outside of `roots`
+/// are skipped. Returns the resolved path or the "no file" message.
+fn search_path(
+    name: &str,
+    path: &str,
+    sep: &str,
+    rep: &str,
+    roots: &[PathBuf],
+) -> Result<PathBuf, String> {
+    let name = if sep.is_empty() {
+        name.to_string()
+    } else {
+        name.replace(sep, rep)
+    };
+
+    let mut tried = Vec::new();
+    for template in path.split(';').filter(|template| !template.is_empty()) {
+        let filename = template.replace('?', &name);
+        let resolved = roots.iter().find_map(|root| jail(root, &filename).ok());
+        match resolved {
+            Some(resolved) if resolved.is_file() => return Ok(resolved),
+            Some(_) => tried.push(format!("no file '{filename}'")),
+            None => tried.push(format!("no file '{filename}' (outside of the sandbox)")),
+        }
+    }
+
+    Err(tried.join("\n\t"))
+}
+
+/// Replace the Lua file searcher of `require` and `package.searchpath`
+/// with confined ones, and remove the C searchers.
+fn confine_package(lua: &mlua::Lua, package: &mlua::Table, root: &Path) -> anyhow::Result<()> {
+    // search roots can change after the session is created
+    let roots = {
+        let root = root.to_path_buf();
+        move || {
+            let mut roots = vec![root.clone()];
+            roots.extend(super::config().module_roots());
+            roots
+        }
+    };
+
+    let searchpath = {
+        let roots = roots.clone();
+        lua.create_function(
+            move |_, (name, path, sep, rep): (String, String, Option<String>, Option<String>)| {
+                let sep = sep.as_deref().unwrap_or(".");
+                let rep = rep.as_deref().unwrap_or("/");
+                Ok(match search_path(&name, &path, sep, rep, &roots()) {
+                    Ok(resolved) => (Some(resolved.to_string_lossy().into_owned()), None),
+                    Err(msg) => (None, Some(msg)),
+                })
+            },
+        )?
+    };
+    package.set("searchpath", searchpath)?;
+
+    let package_ref = package.clone();
+    let searcher = lua.create_function(move |lua, name: String| {
+        let path: String = package_ref.get("path")?;
+        let resolved = match search_path(&name, &path, ".", "/", &roots()) {
+            Ok(resolved) => resolved.to_string_lossy().into_owned(),
+            Err(msg) => return lua.pack_multi(msg),
+        };
+        let func = super::load_file(&resolved).map_err(|e| {
+            mlua::Error::RuntimeError(format!(
+                "error loading module '{name}' from file '{resolved}':\n\t{e:#}"
+            ))
+        })?;
+        lua.pack_multi((func, resolved))
+    })?;
+    let searchers: mlua::Table = package.get("searchers")?;
+    searchers.raw_set(2, searcher)?;
+    // C modules (package.cpath is empty, but it can be changed)
+    searchers.raw_set(4, mlua::Value::Nil)?;
+    searchers.raw_set(3, mlua::Value::Nil)?;
+    block(lua, package, "package.", "loadlib")?;
+
+    Ok(())
+}
+
+pub fn apply(lua: &mlua::Lua, root: &Path) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    if let Ok(io) = globals.get::<mlua::Table>("io") {
+        wrap_paths(lua, &io, "open", &[0], root)?;
+        wrap_paths(lua, &io, "lines", &[0], root)?;
+        wrap_paths(lua, &io, "input", &[0], root)?;
+        wrap_paths(lua, &io, "output", &[0], root)?;
+        block(lua, &io, "io.", "popen")?;
+    }
+    if let Ok(os) = globals.get::<mlua::Table>("os") {
+        wrap_paths(lua, &os, "remove", &[0], root)?;
+        wrap_paths(lua, &os, "rename", &[0, 1], root)?;
+        block(lua, &os, "os.", "execute")?;
+        tmpname(lua, &os, root)?;
+    }
+    wrap_paths(lua, &globals, "loadfile", &[0], root)?;
+    wrap_paths(lua, &globals, "dofile", &[0], root)?;
+    if let Ok(package) = globals.get::<mlua::Table>("package") {
+        confine_package(lua, &package, root)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//! Coroutine scheduler driven by the main loop.
+//!
+//! * `spawn(fn, ...)` runs `fn` as a task until its first wait and
+//!   returns the coroutine.
+//! * `wait([frames])` suspends the task for `frames` frames (default 1).
+//! * `sleep(ms)` suspends the task for `ms` milliseconds.
+//!
+//! A blocking loop would freeze the page, so [tick()] resumes the due
+//! tasks once per frame instead. A plain `coroutine.yield()` in a task
+//! is treated as `wait(1)`. An error in a task is reported and only
+//! that task is dropped.
+//!
+//! Tasks are kept in the app data of the Lua state and are dropped
+//! with the session. Background jobs use [next_wake()] to honor
+//! `wait()` and `sleep()` in the same way.
+
+use crate::emapi;
+
+/// Registry key of the yield marker.
+const TOKEN_KEY: &str = "sched.token";
+
+const WRAPPERS: &str = r#"
+local yield, isyieldable, token = ...
+local function check(name)
+    if not isyieldable() then
+        error(name .. ": not in a task (use spawn)", 3)
+    end
+end
+local function wait(frames)
+    check("wait")
+    return yield(token, "frames", frames or 1)
+end
+local function sleep(ms)
+    check("sleep")
+    return yield(token, "ms", ms or 0)
+end
+return wait, sleep
+"#;
+
+/// When to resume a suspended coroutine.
+pub enum Wake {
+    Frames(u64),
+    /// `performance_now()` (ms)
+    At(f64),
+    /// Input for `io.read()` (see [super::stdin])
+    Input,
+}
+
+impl Wake {
+    /// Called once per frame. Returns `true` if it is time to resume.
+    pub fn is_due(&mut self, now: f64) -> bool {
+        match self {
+            Self::Frames(frames) => {
+                *frames = frames.saturating_sub(1);
+                *frames == 0
+            }
+            Self::At(at) => now >= *at,
+            Self::Input => super::stdin::has_input(),
+        }
+    }
+}
+
+struct Task {
+    thread: mlua::Thread,
+    wake: Wake,
+}
+
+struct Tasks {
+    list: Vec<Task>,
+    /// Pointer of the table which marks yields by `wait()` and `sleep()`.
+    token: usize,
+}
+
+/// Decide when to resume a coroutine from the values it yielded.
+pub fn next_wake(lua: &mlua::Lua, values: &mlua::MultiValue, now: f64) -> Wake {
+    match lua.app_data_ref::<Tasks>() {
+        Some(tasks) => wake_from(values, tasks.token, now),
+        None => Wake::Frames(1),
+    }
+}
+
+fn wake_from(values: &mlua::MultiValue, token: usize, now: f64) -> Wake {
+    let is_ours = values
+        .front()
+        .is_some_and(|v| v.to_pointer() as usize == token);
+    if !is_ours {
+        return Wake::Frames(1);
+    }
+
+    let amount = values.get(2).and_then(mlua::Value::as_f64).unwrap_or(0.0);
+    match values.get(1).and_then(mlua::Value::as_string) {
+        Some(kind) if kind == "ms" => Wake::At(now + amount.max(0.0)),
+        Some(kind) if kind == "read" => Wake::Input,
+        _ => Wake::Frames(amount.max(0.0) as u64),
+    }
+}
+
+fn spawn(
+    lua: &mlua::Lua,
+    (func, args): (mlua::Function, mlua::MultiValue),
+) -> mlua::Result<mlua::Thread> {
+    let thread = lua.create_thread(func)?;
+    let values = thread.resume::<mlua::MultiValue>(args)?;
+
+    if thread.status() == mlua::ThreadStatus::Resumable
+        && let Some(mut tasks) = lua.app_data_mut::<Tasks>()
+    {
+        let wake = wake_from(&values, tasks.token, emapi::emscripten::performance_now());
+        tasks.list.push(Task {
+            thread: thread.clone(),
+            wake,
+        });
+    }
+
+    Ok(thread)
+}
+
+/// Resume the due tasks. Called every frame from the main loop.
+pub fn tick() {
+    let lua = if let Some(lua) = super::current() {
+        lua
+    } else {
+        return;
+    };
+    let (tasks, token) = match lua.app_data_mut::<Tasks>() {
+        Some(mut tasks) if !tasks.list.is_empty() => (std::mem::take(&mut tasks.list), tasks.token),
+        _ => return,
+    };
+
+    let now = emapi::emscripten::performance_now();
+    let mut remaining = Vec::new();
+    for mut task in tasks {
+        if !task.wake.is_due(now) {
+            remaining.push(task);
+            continue;
+        }
+
+        match super::guarded(|| task.thread.resume::<mlua::MultiValue>(())) {
+            Ok(values) => {
+                if task.thread.status() == mlua::ThreadStatus::Resumable {
+                    task.wake = wake_from(&values, token, now);
+                    remaining.push(task);
+                }
+            }
+            Err(err) => {
+                super::report(&err);
+            }
+        }
+    }
+
+    // tasks spawned by the tasks above
+    if let Some(mut tasks) = lua.app_data_mut::<Tasks>() {
+        remaining.append(&mut tasks.list);
+        tasks.list = remaining;
+    }
+}
+
+/// Drop all tasks (e.g. when the script is run again).
+pub fn clear() {
+    if let Some(lua) = super::current()
+        && let Some(mut tasks) = lua.app_data_mut::<Tasks>()
+    {
+        tasks.list.clear();
+    }
+}
+
+/// `true` if any task is waiting for input of `io.read()`.
+pub fn is_reading() -> bool {
+    super::current()
+        .and_then(|lua| {
+            let tasks = lua.app_data_ref::<Tasks>()?;
+            Some(
+                tasks
+                    .list
+                    .iter()
+                    .any(|task| matches!(task.wake, Wake::Input)),
+            )
+        })
+        .unwrap_or(false)
+}
+
+/// Number of the pending tasks.
+pub fn count(lua: &mlua::Lua) -> usize {
+    lua.app_data_ref::<Tasks>()
+        .map_or(0, |tasks| tasks.list.len())
+}
+
+/// The value which marks yields handled by the scheduler:
+/// `coroutine.yield(token, "frames" | "ms" | "read", amount)`.
+/// `None` if the scheduler is disabled.
+pub fn token(lua: &mlua::Lua) -> Option<mlua::Table> {
+    lua.named_registry_value::<Option<mlua::Table>>(TOKEN_KEY)
+        .ok()
+        .flatten()
+}
+
+pub fn open(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let globals = lua.globals();
+    let coroutine = if let Ok(coroutine) = globals.get::<mlua::Table>("coroutine") {
+        coroutine
+    } else {
+        log::debug!("coroutine library is not loaded, scheduler disabled");
+        return Ok(());
+    };
+
+    let token = lua.create_table()?;
+    lua.set_named_registry_value(TOKEN_KEY, token.clone())?;
+    lua.set_app_data(Tasks {
+        list: Vec::new(),
+        token: token.to_pointer() as usize,
+    });
+
+    let (wait, sleep): (mlua::Function, mlua::Function) =
+        lua.load(WRAPPERS).set_name("=sched").call((
+            coroutine.get::<mlua::Function>("yield")?,
+            coroutine.get::<mlua::Function>("isyieldable")?,
+            token,
+        ))?;
+    globals.set("spawn", lua.create_function(spawn)?)?;
+    globals.set("wait", wait)?;
+    globals.set("sleep", sleep)?;
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! Standard input of Lua scripts fed from the command line.
+//!
+//! `io.read()` is replaced so that a script running as a job
+//! (see [crate::app::cmdline::job]) or a task of `spawn()` suspends
+//! until a line is entered instead of blocking the page. Lines typed
+//! while the foreground job or a task is waiting are queued by
+//! [push_line()] rather than executed.
+//!
+//! Outside of a coroutine `io.read()` can only consume already queued
+//! input and raises an error otherwise.
+//! Supported formats: "l", "L", "n", "a" and a byte count.
+
+use std::cell::RefCell;
+
+thread_local! {
+    /// Queued input (each line ends with '\n')
+    static BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+const WRAPPER: &str = r#"
+local yield, isyieldable, token, ready, take = ...
+return function(...)
+    while not ready(...) do
+        if not (isyieldable and isyieldable()) then
+            error("io.read: no input (use `run` to run interactive scripts)", 2)
+        end
+        yield(token, "read")
+    end
+    return take(...)
+end
+"#;
+
+pub fn push_line(line: &str) {
+    BUFFER.with(|cell| {
+        let mut buf = cell.borrow_mut();
+        buf.push_str(line);
+        buf.push('\n');
+    });
+}
+
+/// Discard the queued input (e.g. the reader was killed).
+pub fn clear() {
+    BUFFER.with(|cell| cell.borrow_mut().clear());
+}
+
+pub fn has_input() -> bool {
+    BUFFER.with(|cell| !cell.borrow().is_empty())
+}
+
+/// Take a line (including '\n') if a whole line is queued.
+fn take_line(buf: &mut String) -> Option<String> {
+    let end = buf.find('\n')? + 1;
+    Some(buf.drain(..end).collect())
+}
+
+/// Read one format from `buf`. `None` if the input is not enough yet.
+fn read_one(
+    lua: &mlua::Lua,
+    buf: &mut String,
+    format: &mlua::Value,
+) -> mlua::Result<Option<mlua::Value>> {
+    use mlua::IntoLua;
+
+    if let Some(count) = format.as_i64() {
+        if buf.is_empty() && count > 0 {
+            return Ok(None);
+        }
+        let mut end = (count.max(0) as usize).min(buf.len());
+        while !buf.is_char_boundary(end) {
+            end += 1;
+        }
+        let s: String = buf.drain(..end).collect();
+        return Ok(Some(s.into_lua(lua)?));
+    }
+
+    let format = match format {
+        mlua::Value::Nil => "l".to_string(),
+        mlua::Value::String(s) => s.to_str()?.trim_start_matches('*').to_string(),
+        _ => {
+            return Err(mlua::Error::RuntimeError(
+                "bad argument to 'read' (invalid format)".to_string(),
+            ));
+        }
+    };
+    let value = match format.chars().next() {
+        Some('l') => {
+            take_line(buf).map(|line| line.trim_end_matches('\n').to_string().into_lua(lua))
+        }
+        Some('L') => take_line(buf).map(|line| line.into_lua(lua)),
+        Some('n') => take_line(buf).map(|line| {
+            let line = line.trim();
+            if let Ok(n) = line.parse::<i64>() {
+                Ok(mlua::Value::Integer(n))
+            } else if let Ok(n) = line.parse::<f64>() {
+                Ok(mlua::Value::Number(n))
+            } else {
+                Ok(mlua::Value::Nil)
+            }
+        }),
+        Some('a') if !buf.is_empty() => Some(std::mem::take(buf).into_lua(lua)),
+        Some('a') => None,
+        _ => {
+            return Err(mlua::Error::RuntimeError(format!(
+                "bad argument to 'read' (invalid format '{format}')"
+            )));
+        }
+    };
+
+    value.transpose()
+}
+
+/// Read all `formats` from `buf`. `None` if the input is not enough yet.
+fn read_all(
+    lua: &mlua::Lua,
+    buf: &mut String,
+    formats: &mlua::MultiValue,
+) -> mlua::Result<Option<mlua::MultiValue>> {
+    let mut values = mlua::MultiValue::new();
+    if formats.is_empty() {
+        return Ok(read_one(lua, buf, &mlua::Value::Nil)?.map(|v| {
+            values.push_back(v);
+            values
+        }));
+    }
+
+    for format in formats.iter() {
+        match read_one(lua, buf, format)? {
+            Some(v) => values.push_back(v),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(values))
+}
+
+pub fn open(lua: &mlua::Lua) -> anyhow::Result<()> {
+    let io = if let Ok(io) = lua.globals().get::<mlua::Table>("io") {
+        io
+    } else {
+        return Ok(());
+    };
+    let coroutine = lua.globals().get::<Option<mlua::Table>>("coroutine")?;
+    let (yield_, isyieldable) = match &coroutine {
+        Some(coroutine) => (
+            coroutine.get::<Option<mlua::Function>>("yield")?,
+            coroutine.get::<Option<mlua::Function>>("isyieldable")?,
+        ),
+        None => (None, None),
+    };
+
+    // check on a copy first (nothing is consumed unless all formats are satisfied)
+    let ready = lua.create_function(|lua, formats: mlua::MultiValue| {
+        let mut buf = BUFFER.with(|cell| cell.borrow().clone());
+        Ok(read_all(lua, &mut buf, &formats)?.is_some())
+    })?;
+    let take = lua.create_function(|lua, formats: mlua::MultiValue| {
+        let mut buf = BUFFER.with(|cell| cell.take());
+        let res = read_all(lua, &mut buf, &formats);
+        // put back the rest
+        BUFFER.with(|cell| cell.borrow_mut().insert_str(0, &buf));
+        Ok(res?.unwrap_or_else(mlua::MultiValue::new))
+    })?;
+
+    let read: mlua::Function = lua.load(WRAPPER).set_name("=stdin").call((
+        yield_,
+        isyieldable,
+        super::sched::token(lua),
+        ready,
+        take,
+    ))?;
+    io.set("read", read)?;
+
+    Ok(())
+}
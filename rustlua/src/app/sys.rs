@@ -31,7 +31,10 @@ fn process_cmdline() -> anyhow::Result<()> {
     let cmdline = cmdline.unwrap();
     log::info!("EXEC: {cmdline}");
 
-    super::cmdline::exec(&cmdline)
+    let status = super::cmdline::exec(&cmdline);
+    log::info!("EXIT: {status}");
+
+    Ok(())
 }
 
 fn process_import_file() -> anyhow::Result<()> {
@@ -90,10 +93,6 @@ fn process_import_file() -> anyhow::Result<()> {
 }
 
 pub fn lua_exec(src: &str) -> anyhow::Result<()> {
-    let libs = mlua::StdLib::ALL_SAFE;
-    let options = mlua::LuaOptions::new().catch_rust_panics(true);
-    let lua = mlua::Lua::new_with(libs, options)?;
-
     /*
      * source: the source of the chunk that created the function.
      * If source starts with a '@', it means that the function was defined
@@ -103,13 +102,10 @@ pub fn lua_exec(src: &str) -> anyhow::Result<()> {
      * Otherwise, the function was defined in a string
      * where source is that string.
      */
-    // not compiled yet
     // by default, Rust file:line:column (for panic) will be used for name
-    let chunk = lua.load(src).set_name("=<STR_SRC>");
-    // compile and execute
-    chunk.exec()?;
-
-    Ok(())
+    // the Lua state is kept in the session and reused for the next run
+    // "editor" is used by the page to highlight the error line
+    super::lua::exec(src, "=editor")
 }
 
 fn set_callback_button_clicked() {
@@ -119,7 +115,7 @@ fn set_callback_button_clicked() {
             r"
 (() => {
     if (!document) return null;
-    var e = document.getElementById('source');
+    var e = document.getElementById('edit');
     if (!e) return null;
     return e.value;
 })()
@@ -132,13 +128,23 @@ fn set_callback_button_clicked() {
             return false;
         };
 
+        super::game::stop();
+        super::lua::sched::clear();
+        if let Err(err) = super::game::clear_callbacks() {
+            log::error!("{err:#}");
+        }
         match lua_exec(&src) {
             Ok(()) => {
                 println!("Lua executed successfully");
+                match super::game::start() {
+                    Ok(true) => println!("Game loop started"),
+                    Ok(false) => {}
+                    Err(err) => eprintln!("{err:#}"),
+                }
                 println!();
             }
             Err(err) => {
-                println!("{err}");
+                super::lua::report(&err);
                 println!();
             }
         }
@@ -185,6 +191,7 @@ fn main_loop(surface: &emapi::sdl::Surface) {
 
 fn main_loop_raw(
     surface: &emapi::sdl::Surface,
+    font: &emapi::sdl::ttf::Font,
     numfont: &emapi::sdl::Surface,
     img: &emapi::sdl::Surface,
     se: &emapi::sdl::mixer::Chunk,
@@ -222,18 +229,38 @@ fn main_loop_raw(
             state.frame_count = 0;
             log::trace!("fps: {:.1}", state.fps);
 
-            let b = se.play();
-            log::info!("play se: {b}");
+            if !super::game::is_active() {
+                let b = se.play();
+                log::info!("play se: {b}");
+            }
         }
 
         state.fps
     });
 
-    // update & render
-    main_loop(surface);
-    // draw fps
-    img.blit(surface).expect("blit failed");
-    numfont.blit(surface).expect("blit failed");
+    let events = emapi::sdl::event::poll_events();
+    for event in &events {
+        log::trace!("{event:?}");
+    }
+    super::lua::input::begin_frame(&events);
+    // resume coroutines of spawn()
+    super::lua::sched::tick();
+    // background jobs of `run`
+    super::cmdline::job::step();
+
+    // drawing by Lua commands (outside the game loop)
+    super::lua::gfx::finish();
+    if super::game::is_active() {
+        // Lua update() and draw()
+        update();
+        super::game::frame(surface, font, &events);
+    } else {
+        // update & render
+        main_loop(surface);
+        // draw fps
+        img.blit(surface).expect("blit failed");
+        numfont.blit(surface).expect("blit failed");
+    }
     // show as main canvas
     surface.flip().expect("flip failed");
 }
@@ -265,7 +292,7 @@ fn setup_main_loop() -> anyhow::Result<()> {
     // probably because of security issue?
     // fps=0 means to use requestAnimationFrame()
     emapi::emscripten::set_main_loop(0, move || {
-        main_loop_raw(&surface, &numfont, &img, &se);
+        main_loop_raw(&surface, &font, &numfont, &img, &se);
     });
 
     Ok(())
@@ -66,6 +66,13 @@ pub fn performance_now() -> f64 {
     unsafe { ffi::emscripten_performance_now() }
 }
 
+/// Current size of the WASM linear memory. (bytes)
+pub fn wasm_memory_size() -> usize {
+    const PAGE_SIZE: usize = 64 * 1024;
+
+    core::arch::wasm32::memory_size::<0>() * PAGE_SIZE
+}
+
 /// <https://emscripten.org/docs/api_reference/emscripten.h.html#c.emscripten_set_main_loop>
 pub fn set_main_loop<F>(fps: i32, func: F)
 where
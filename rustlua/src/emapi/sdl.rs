@@ -1,4 +1,5 @@
 use std::ffi::CStr;
+use std::mem::ManuallyDrop;
 
 mod ffi {
     #![allow(dead_code)]
@@ -20,7 +21,7 @@ fn sdl_error() -> anyhow::Result<()> {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -36,6 +37,7 @@ impl Color {
         g: 255,
         b: 255,
     };
+    pub const RED: Self = Self { r: 255, g: 0, b: 0 };
 
     fn to_sdl_color(&self) -> ffi::SDL_Color {
         ffi::SDL_Color {
@@ -47,6 +49,25 @@ impl Color {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Rect {
+    fn to_sdl_rect(self) -> ffi::SDL_Rect {
+        ffi::SDL_Rect {
+            x: self.x as _,
+            y: self.y as _,
+            w: self.w as _,
+            h: self.h as _,
+        }
+    }
+}
+
 pub fn init() -> anyhow::Result<()> {
     let ret = unsafe { ffi::SDL_Init(ffi::SDL_INIT_EVERYTHING) };
     if ret < 0 {
@@ -122,6 +143,33 @@ impl Surface {
         (s.w, s.h, s.pitch)
     }
 
+    pub fn width(&self) -> i32 {
+        self.deref().w
+    }
+
+    pub fn height(&self) -> i32 {
+        self.deref().h
+    }
+
+    pub fn pitch(&self) -> i32 {
+        self.deref().pitch
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        let format = unsafe { &*self.deref().format };
+        format.BytesPerPixel as usize
+    }
+
+    /// Convert a pixel value of this surface to [Color].
+    pub fn get_rgb(&self, pixel: u32) -> Color {
+        let (mut r, mut g, mut b) = (0, 0, 0);
+        unsafe {
+            ffi::SDL_GetRGB(pixel, self.deref().format, &mut r, &mut g, &mut b);
+        }
+
+        Color { r, g, b }
+    }
+
     pub fn pixels(&self) -> &mut [u8] {
         let s = self.deref();
         let len = (s.pitch * s.h) as usize;
@@ -170,6 +218,66 @@ impl Surface {
 
         Ok(())
     }
+
+    /// Blit the whole surface to (x, y) of `dst`.
+    pub fn blit_to(&self, dst: &Self, x: i32, y: i32) -> anyhow::Result<()> {
+        // w and h of dstrect are ignored
+        let mut dstrect = ffi::SDL_Rect {
+            x: x as _,
+            y: y as _,
+            w: 0,
+            h: 0,
+        };
+        let ret = unsafe { ffi::SDL_UpperBlit(self.0, std::ptr::null(), dst.0, &mut dstrect) };
+        if ret < 0 {
+            sdl_error()?;
+        }
+
+        Ok(())
+    }
+
+    /// Blit `srcrect` of this surface to (x, y) of `dst`.
+    pub fn blit_rect_to(&self, srcrect: Rect, dst: &Self, x: i32, y: i32) -> anyhow::Result<()> {
+        let srcrect = srcrect.to_sdl_rect();
+        let mut dstrect = ffi::SDL_Rect {
+            x: x as _,
+            y: y as _,
+            w: 0,
+            h: 0,
+        };
+        let ret = unsafe { ffi::SDL_UpperBlit(self.0, &srcrect, dst.0, &mut dstrect) };
+        if ret < 0 {
+            sdl_error()?;
+        }
+
+        Ok(())
+    }
+
+    /// Convert `color` to a pixel value of this surface.
+    pub fn map_rgb(&self, color: Color) -> u32 {
+        unsafe { ffi::SDL_MapRGB(self.deref().format, color.r, color.g, color.b) }
+    }
+
+    /// Fill the whole surface. (must not be locked)
+    pub fn fill(&self, color: Color) -> anyhow::Result<()> {
+        let ret = unsafe { ffi::SDL_FillRect(self.0, std::ptr::null_mut(), self.map_rgb(color)) };
+        if ret < 0 {
+            sdl_error()?;
+        }
+
+        Ok(())
+    }
+
+    /// Fill a rectangle. (must not be locked)
+    pub fn fill_rect(&self, rect: Rect, color: Color) -> anyhow::Result<()> {
+        let mut rect = rect.to_sdl_rect();
+        let ret = unsafe { ffi::SDL_FillRect(self.0, &mut rect, self.map_rgb(color)) };
+        if ret < 0 {
+            sdl_error()?;
+        }
+
+        Ok(())
+    }
 }
 
 pub fn set_video_mode(width: i32, height: i32, bpp: i32, flags: u32) -> anyhow::Result<Surface> {
@@ -181,6 +289,18 @@ pub fn set_video_mode(width: i32, height: i32, bpp: i32, flags: u32) -> anyhow::
     Ok(Surface(surface))
 }
 
+/// Get the surface returned by [set_video_mode()].
+///
+/// It must not be freed, so it is wrapped by [ManuallyDrop].
+pub fn get_video_surface() -> Option<ManuallyDrop<Surface>> {
+    let surface = unsafe { ffi::SDL_GetVideoSurface() };
+    if !surface.is_null() {
+        Some(ManuallyDrop::new(Surface(surface)))
+    } else {
+        None
+    }
+}
+
 // -----------------------------------------------------------------------------
 
 pub mod ttf {
@@ -211,7 +331,7 @@ pub mod ttf {
 
     impl Font {
         pub fn render(&self, text: &str, fg: super::Color) -> anyhow::Result<super::Surface> {
-            let text = CString::new(text).unwrap();
+            let text = CString::new(text)?;
             let surface =
                 unsafe { ffi::TTF_RenderText_Blended(self.0, text.as_ptr(), fg.to_sdl_color()) };
             if surface.is_null() {
@@ -220,6 +340,18 @@ pub mod ttf {
 
             Ok(super::Surface(surface))
         }
+
+        /// Get the size (width, height) of rendered `text`.
+        pub fn size_text(&self, text: &str) -> anyhow::Result<(i32, i32)> {
+            let text = CString::new(text)?;
+            let (mut w, mut h) = (0, 0);
+            let ret = unsafe { ffi::TTF_SizeText(self.0, text.as_ptr(), &mut w, &mut h) };
+            if ret < 0 {
+                ttf_error()?;
+            }
+
+            Ok((w, h))
+        }
     }
 
     pub fn open_font(file: &str, ptsize: i32) -> anyhow::Result<Font> {
@@ -329,6 +461,56 @@ pub mod mixer {
 
             ret >= 0
         }
+
+        /// Play on the first free channel and return the channel number.
+        /// `loops` is the number of extra plays (-1: forever).
+        ///
+        /// Emscripten SDL_mixer only supports 0 and forever.
+        pub fn play_channel(&self, loops: i32) -> anyhow::Result<i32> {
+            let channel = unsafe { ffi::Mix_PlayChannelTimed(-1, self.0, loops, -1) };
+            if channel < 0 {
+                mixer_error().context("Mix_PlayChannel failed")?;
+            }
+
+            Ok(channel)
+        }
+    }
+
+    /// Max volume of [set_volume()].
+    pub const MAX_VOLUME: i32 = ffi::MIX_MAX_VOLUME as i32;
+
+    pub fn halt(channel: i32) {
+        unsafe {
+            ffi::Mix_HaltChannel(channel);
+        }
+    }
+
+    pub fn pause(channel: i32) {
+        unsafe {
+            ffi::Mix_Pause(channel);
+        }
+    }
+
+    pub fn resume(channel: i32) {
+        unsafe {
+            ffi::Mix_Resume(channel);
+        }
+    }
+
+    /// `volume`: 0..=[MAX_VOLUME]
+    pub fn set_volume(channel: i32, volume: i32) {
+        unsafe {
+            ffi::Mix_Volume(channel, volume.clamp(0, MAX_VOLUME));
+        }
+    }
+
+    /// Emscripten SDL_mixer returns `false` while paused.
+    pub fn is_playing(channel: i32) -> bool {
+        unsafe { ffi::Mix_Playing(channel) != 0 }
+    }
+
+    pub fn is_paused(channel: i32) -> bool {
+        unsafe { ffi::Mix_Paused(channel) != 0 }
     }
 
     pub fn load(file: &str) -> anyhow::Result<Chunk> {
@@ -350,3 +532,194 @@ pub mod mixer {
         load(TMP_PATH)
     }
 }
+
+// -----------------------------------------------------------------------------
+
+pub mod event {
+    use super::ffi;
+
+    /// Key modifier bits (`SDL_Keymod`).
+    #[allow(dead_code)]
+    pub mod keymod {
+        use super::ffi;
+
+        pub const SHIFT: u16 = (ffi::SDL_Keymod_KMOD_LSHIFT | ffi::SDL_Keymod_KMOD_RSHIFT) as u16;
+        pub const CTRL: u16 = (ffi::SDL_Keymod_KMOD_LCTRL | ffi::SDL_Keymod_KMOD_RCTRL) as u16;
+        pub const ALT: u16 = (ffi::SDL_Keymod_KMOD_LALT | ffi::SDL_Keymod_KMOD_RALT) as u16;
+        pub const GUI: u16 = (ffi::SDL_Keymod_KMOD_LGUI | ffi::SDL_Keymod_KMOD_RGUI) as u16;
+    }
+
+    /// Names of non-printable keys (as in LÖVE).
+    /// `SDL_GetKeyName()` of Emscripten returns "unknown key" for any key.
+    const KEY_NAMES: &[(i32, &str)] = &[
+        (ffi::SDLK_RETURN as i32, "return"),
+        (ffi::SDLK_ESCAPE as i32, "escape"),
+        (ffi::SDLK_BACKSPACE as i32, "backspace"),
+        (ffi::SDLK_TAB as i32, "tab"),
+        (ffi::SDLK_SPACE as i32, "space"),
+        (ffi::SDLK_DELETE as i32, "delete"),
+        (ffi::SDLK_INSERT as i32, "insert"),
+        (ffi::SDLK_HOME as i32, "home"),
+        (ffi::SDLK_END as i32, "end"),
+        (ffi::SDLK_PAGEUP as i32, "pageup"),
+        (ffi::SDLK_PAGEDOWN as i32, "pagedown"),
+        (ffi::SDLK_LEFT as i32, "left"),
+        (ffi::SDLK_RIGHT as i32, "right"),
+        (ffi::SDLK_UP as i32, "up"),
+        (ffi::SDLK_DOWN as i32, "down"),
+        (ffi::SDLK_LSHIFT as i32, "lshift"),
+        (ffi::SDLK_RSHIFT as i32, "rshift"),
+        (ffi::SDLK_LCTRL as i32, "lctrl"),
+        (ffi::SDLK_RCTRL as i32, "rctrl"),
+        (ffi::SDLK_LALT as i32, "lalt"),
+        (ffi::SDLK_RALT as i32, "ralt"),
+        (ffi::SDLK_LGUI as i32, "lgui"),
+        (ffi::SDLK_RGUI as i32, "rgui"),
+        (ffi::SDLK_CAPSLOCK as i32, "capslock"),
+        (ffi::SDLK_F1 as i32, "f1"),
+        (ffi::SDLK_F2 as i32, "f2"),
+        (ffi::SDLK_F3 as i32, "f3"),
+        (ffi::SDLK_F4 as i32, "f4"),
+        (ffi::SDLK_F5 as i32, "f5"),
+        (ffi::SDLK_F6 as i32, "f6"),
+        (ffi::SDLK_F7 as i32, "f7"),
+        (ffi::SDLK_F8 as i32, "f8"),
+        (ffi::SDLK_F9 as i32, "f9"),
+        (ffi::SDLK_F10 as i32, "f10"),
+        (ffi::SDLK_F11 as i32, "f11"),
+        (ffi::SDLK_F12 as i32, "f12"),
+    ];
+
+    /// Key name of `sym`: a printable ASCII character ("a", "1", "/")
+    /// or a name such as "left", "space" and "lshift".
+    pub fn key_name(sym: i32) -> Option<String> {
+        if let Some((_, name)) = KEY_NAMES.iter().find(|(s, _)| *s == sym) {
+            return Some(name.to_string());
+        }
+
+        u8::try_from(sym)
+            .ok()
+            .filter(u8::is_ascii_graphic)
+            .map(|c| (c as char).to_ascii_lowercase().to_string())
+    }
+
+    /// Inverse of [key_name()].
+    pub fn key_from_name(name: &str) -> Option<i32> {
+        if let Some((sym, _)) = KEY_NAMES.iter().find(|(_, n)| *n == name) {
+            return Some(*sym);
+        }
+
+        match name.as_bytes() {
+            [c] if c.is_ascii_graphic() => Some(c.to_ascii_lowercase() as i32),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug, Clone, Copy)]
+    pub enum Event {
+        KeyDown {
+            /// `SDLK_*`
+            sym: i32,
+            /// [keymod] bits
+            mods: u16,
+        },
+        KeyUp {
+            sym: i32,
+            mods: u16,
+        },
+        MouseMotion {
+            x: i32,
+            y: i32,
+            xrel: i32,
+            yrel: i32,
+            /// Pressed buttons (bit 0: left, bit 1: middle, bit 2: right)
+            state: u32,
+        },
+        MouseButtonDown {
+            /// 1: left, 2: middle, 3: right
+            button: u8,
+            x: i32,
+            y: i32,
+        },
+        MouseButtonUp {
+            button: u8,
+            x: i32,
+            y: i32,
+        },
+        Quit,
+        Resize {
+            w: i32,
+            h: i32,
+        },
+    }
+
+    fn convert(ev: &ffi::SDL_Event) -> Option<Event> {
+        // the active member is selected by `type_`
+        unsafe {
+            let event = match ev.type_ {
+                ffi::SDL_EventType_SDL_KEYDOWN => Event::KeyDown {
+                    sym: ev.key.keysym.sym as i32,
+                    mods: ev.key.keysym.mod_ as u16,
+                },
+                ffi::SDL_EventType_SDL_KEYUP => Event::KeyUp {
+                    sym: ev.key.keysym.sym as i32,
+                    mods: ev.key.keysym.mod_ as u16,
+                },
+                ffi::SDL_EventType_SDL_MOUSEMOTION => Event::MouseMotion {
+                    x: ev.motion.x as i32,
+                    y: ev.motion.y as i32,
+                    xrel: ev.motion.xrel as i32,
+                    yrel: ev.motion.yrel as i32,
+                    state: ev.motion.state as u32,
+                },
+                ffi::SDL_EventType_SDL_MOUSEBUTTONDOWN => Event::MouseButtonDown {
+                    button: ev.button.button as u8,
+                    x: ev.button.x as i32,
+                    y: ev.button.y as i32,
+                },
+                ffi::SDL_EventType_SDL_MOUSEBUTTONUP => Event::MouseButtonUp {
+                    button: ev.button.button as u8,
+                    x: ev.button.x as i32,
+                    y: ev.button.y as i32,
+                },
+                ffi::SDL_EventType_SDL_QUIT => Event::Quit,
+                ffi::SDL_EventType_SDL_WINDOWEVENT
+                    if ev.window.event as u32 == ffi::SDL_WindowEventID_SDL_WINDOWEVENT_RESIZED =>
+                {
+                    Event::Resize {
+                        w: ev.window.data1 as i32,
+                        h: ev.window.data2 as i32,
+                    }
+                }
+                // SDL_VIDEORESIZE (SDL 1.2 compatible, sent by Emscripten)
+                ffi::SDL_EventType_SDL_EVENT_COMPAT2 => Event::Resize {
+                    w: ev.resize.w as i32,
+                    h: ev.resize.h as i32,
+                },
+                _ => return None,
+            };
+
+            Some(event)
+        }
+    }
+
+    /// Drain the SDL event queue.
+    /// Unsupported event types are skipped.
+    pub fn poll_events() -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            let mut ev = std::mem::MaybeUninit::<ffi::SDL_Event>::zeroed();
+            let ret = unsafe { ffi::SDL_PollEvent(ev.as_mut_ptr()) };
+            if ret == 0 {
+                break;
+            }
+            let ev = unsafe { ev.assume_init() };
+            if let Some(event) = convert(&ev) {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+}